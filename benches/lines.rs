@@ -0,0 +1,173 @@
+//! Memory and lookup speed of line tables, `Vec<usize>` against `CompactLines`,
+//! on their own and as the line starts of a `LineIndex` built by a `Stream`.
+//!
+//! Run with `cargo bench --bench lines`, set `LINES_M` to change the number of lines (millions).
+use std::hint::black_box;
+use std::io;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+use stream_locate_converter::location::Offset;
+use stream_locate_converter::{CompactLines, LineIndex, Stream};
+
+/// Starts of many small lines
+fn starts(n: usize) -> Vec<usize> {
+    let mut starts = Vec::with_capacity(n);
+    let mut start = 0;
+    for i in 0..n {
+        starts.push(start);
+        start += 1 + i * 7919 % 80;
+    }
+    starts
+}
+
+/// Input whose lines start at `starts`, generated while it is read
+struct Input<'a> {
+    starts: &'a [usize],
+    pos: usize,
+    /// Line of `pos`
+    line: usize,
+}
+
+impl io::Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() && self.line + 1 < self.starts.len() {
+            let end = self.starts[self.line + 1];
+            let len = (end - self.pos).min(buf.len() - n);
+            buf[n..n + len].fill(b'x');
+            self.pos += len;
+            n += len;
+            if self.pos == end {
+                buf[n - 1] = b'\n';
+                self.line += 1;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Index the input of `starts`, its last line is empty
+fn index(starts: &[usize], compact: bool) -> LineIndex {
+    let input = Input {
+        starts,
+        pos: 0,
+        line: 0,
+    };
+    let stream = Stream::new(input, 1 << 16);
+    let stream = if compact { stream.with_compact_lines() } else { stream };
+    stream.into_index().unwrap()
+}
+
+/// Offsets to look up, spread over the input
+fn offsets(end: usize, n: usize) -> Vec<usize> {
+    let mut x = 0x9e37_79b9_7f4a_7c15u64;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as usize % end
+        })
+        .collect()
+}
+
+/// The search `Stream` ran on its line table before it moved to `partition_point`
+fn binary_search_between(xs: &[usize], x: usize) -> Option<usize> {
+    if xs.is_empty() {
+        return None;
+    }
+    if x == 0 {
+        return Some(0);
+    }
+
+    let mut start = 0;
+    let mut end = xs.len() - 1;
+    while start < end {
+        if start == end - 1 && xs[start] <= x && x < xs[end] {
+            return Some(start);
+        }
+        let mid = start + ((end - start) >> 1);
+        let y = xs[mid];
+        if x == y {
+            return Some(mid);
+        }
+        if x < y {
+            end = mid;
+            continue;
+        }
+        if start == mid {
+            return None;
+        }
+        start = mid;
+    }
+    None
+}
+
+/// Best of a few runs
+fn measure(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            let sum = black_box(f());
+            (start.elapsed(), sum)
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let m: usize = std::env::var("LINES_M").ok().and_then(|s| s.parse().ok()).unwrap_or(8);
+    let starts = starts(m * 1_000_000);
+    let compact = CompactLines::from(&starts[..]);
+    let (plain_index, compact_index) = (index(&starts, false), index(&starts, true));
+    assert_eq!(plain_index.line_count(), starts.len());
+    // Offsets past the last line start are not found by `binary_search_between`
+    let queries = offsets(*starts.last().unwrap(), 1_000_000);
+    let rate = |elapsed: Duration| queries.len() as f64 / elapsed.as_secs_f64() / 1e6;
+
+    let (vec, vec_sum) = measure(|| {
+        let search = |&offset| binary_search_between(&starts, offset).unwrap();
+        queries.iter().map(search).sum()
+    });
+    let (partition, partition_sum) = measure(|| {
+        let search = |&offset| starts.partition_point(|&start| start <= offset) - 1;
+        queries.iter().map(search).sum()
+    });
+    let (packed, packed_sum) = measure(|| {
+        queries.iter().map(|&offset| compact.search(offset).unwrap()).sum()
+    });
+    let line_of = |index: &LineIndex| {
+        let search = |&offset| index.line_of(Offset::new(offset)).unwrap();
+        queries.iter().map(search).sum()
+    };
+    let (plain_lookup, plain_sum) = measure(|| line_of(&plain_index));
+    let (compact_lookup, compact_sum) = measure(|| line_of(&compact_index));
+    assert_eq!(vec_sum, partition_sum);
+    assert_eq!(vec_sum, packed_sum);
+    assert_eq!(vec_sum, plain_sum);
+    assert_eq!(vec_sum, compact_sum);
+
+    let mib = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+    println!("{} lines", starts.len());
+    println!(
+        "Vec<usize>    {:>8.1} MiB   binary_search_between {:>6.1} M/s   partition_point {:>6.1} M/s",
+        mib(starts.len() * size_of::<usize>()),
+        rate(vec),
+        rate(partition),
+    );
+    println!(
+        "CompactLines  {:>8.1} MiB   search                {:>6.1} M/s",
+        mib(compact.heap_size()),
+        rate(packed),
+    );
+    println!(
+        "LineIndex     {:>8.1} MiB   line_of               {:>6.1} M/s",
+        mib(plain_index.lines_heap_size()),
+        rate(plain_lookup),
+    );
+    println!(
+        "  compact     {:>8.1} MiB   line_of               {:>6.1} M/s",
+        mib(compact_index.lines_heap_size()),
+        rate(compact_lookup),
+    );
+}
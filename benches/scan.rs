@@ -0,0 +1,85 @@
+//! Line indexing throughput, compared with the byte-at-a-time loop the scanner used to run.
+//!
+//! Run with `cargo bench --bench scan`, set `SCAN_MB` to change the input size.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use stream_locate_converter::line_ending::LineEnding;
+use stream_locate_converter::{ParallelIndexer, Stream};
+
+/// Log-like lines of varying length
+fn input(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len + 128);
+    let mut i = 0u64;
+    while bytes.len() < len {
+        let width = (i * 7919 % 120) as usize;
+        bytes.extend_from_slice(format!("{:08} INFO request ", i).as_bytes());
+        bytes.resize(bytes.len() + width, b'x');
+        bytes.extend_from_slice(b"\r\n");
+        i += 1;
+    }
+    bytes
+}
+
+/// The previous scanner: every byte is matched
+fn scalar(bytes: &[u8], ending: LineEnding) -> Vec<usize> {
+    let mut lines = vec![0];
+    let mut prev = [0u8; 2];
+    for (pos, &b) in bytes.iter().enumerate() {
+        let [prev2, prev1] = prev;
+        let cr = ending != LineEnding::Lf && prev1 == b'\r';
+        let lone_cr = matches!(ending, LineEnding::Any | LineEnding::Unicode);
+        match b {
+            b'\n' => lines.push(pos + 1),
+            _ if cr && lone_cr => lines.push(pos),
+            0x85 if ending == LineEnding::Unicode && prev1 == 0xC2 => lines.push(pos + 1),
+            0xA8 | 0xA9 if ending == LineEnding::Unicode && [prev2, prev1] == [0xE2, 0x80] => {
+                lines.push(pos + 1)
+            }
+            _ => {}
+        }
+        prev = [prev1, b];
+    }
+    lines
+}
+
+fn indexed(bytes: &[u8], ending: LineEnding) -> usize {
+    let stream = Stream::new(bytes, 64 * 1024).with_line_ending(ending);
+    stream.into_index().unwrap().line_count()
+}
+
+/// Best of a few runs
+fn measure(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            let lines = black_box(f());
+            (start.elapsed(), lines)
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mb: usize = std::env::var("SCAN_MB").ok().and_then(|s| s.parse().ok()).unwrap_or(256);
+    let bytes = input(mb << 20);
+    let throughput = |elapsed: Duration| {
+        bytes.len() as f64 / elapsed.as_secs_f64() / (1 << 20) as f64
+    };
+
+    for ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Any, LineEnding::Unicode] {
+        let (old, old_lines) = measure(|| scalar(&bytes, ending).len());
+        let (new, new_lines) = measure(|| indexed(&bytes, ending));
+        let indexer = ParallelIndexer::new().with_line_ending(ending);
+        let (parallel, parallel_lines) = measure(|| indexer.build(&bytes).line_count());
+        assert_eq!(old_lines, new_lines);
+        assert_eq!(old_lines, parallel_lines);
+        println!(
+            "{:<8} scalar {:>8.0} MiB/s   stream {:>8.0} MiB/s   {:.1}x   parallel {:>8.0} MiB/s",
+            format!("{:?}", ending),
+            throughput(old),
+            throughput(new),
+            old.as_secs_f64() / new.as_secs_f64(),
+            throughput(parallel),
+        );
+    }
+}
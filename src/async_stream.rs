@@ -0,0 +1,225 @@
+//! Asynchronous counterpart of [`crate::Stream`] over [`futures_io::AsyncRead`].
+//!
+//! Tokio readers can be adapted with `tokio_util::compat`.
+use crate::column::ColumnUnit;
+use crate::index::{LineIndex, Need};
+use crate::line_ending::LineEnding;
+use crate::location::{line_column, Offset, Span};
+use crate::stream::Result;
+use futures_io::AsyncRead;
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+
+/// An asynchronous stream which can be used to convert between offsets and line-column numbers.
+#[derive(Debug)]
+pub struct AsyncStream<Reader> {
+    reader: Reader,
+    index: LineIndex,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R> AsyncStream<R> {
+    const BUF_SIZE: usize = 1024;
+
+    #[inline]
+    pub fn line_offset(&self, line: usize) -> Option<Offset> {
+        self.index.line_offset(line)
+    }
+
+    /// Number of lines read so far
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.index.line_count()
+    }
+
+    /// Read length
+    #[inline]
+    pub fn read_len(&self) -> usize {
+        self.index.len
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.index.column_unit()
+    }
+
+    /// Set the unit in which columns are counted.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_column_unit(mut self, unit: ColumnUnit) -> Self {
+        self.index.set_column_unit(unit);
+        self
+    }
+
+    /// Which byte sequences terminate a line
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.index.line_ending()
+    }
+
+    /// Set which byte sequences terminate a line.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_line_ending(mut self, ending: LineEnding) -> Self {
+        self.index.set_line_ending(ending);
+        self
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncStream<R> {
+    pub fn new(reader: R, buffer_size: usize) -> Self {
+        Self {
+            reader,
+            index: LineIndex::default(),
+            buffer: vec![0; buffer_size],
+            eof: false,
+        }
+    }
+
+    #[inline]
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(reader, Self::BUF_SIZE)
+    }
+
+    /// Get offset from line and column number, the column is counted in [`Self::column_unit`]
+    pub async fn offset_of(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Column(line_index.line)).await?;
+        self.index.offset_of(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// fails with [`crate::stream::Error::ColumnOutOfRange`] if the column exceeds the line length (excluding the line break)
+    pub async fn offset_of_strict(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Line(line_index.line)).await?;
+        self.index.offset_of_strict(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub async fn offset_of_clamped(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Line(line_index.line)).await?;
+        self.index.offset_of_clamped(line_index)
+    }
+
+    /// Get line and column number from offset, the column is counted in [`Self::column_unit`]
+    pub async fn line_index(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        let line = self.line_of(offset).await?;
+        self.fill(Need::Column(line)).await?;
+        Ok((line, self.index.column(line, offset.raw())).into())
+    }
+
+    /// Get start and end line-column locations of a span, the end is exclusive and may be EOF
+    pub async fn span_location(
+        &mut self,
+        span: Span,
+    ) -> Result<(line_column::ZeroBased, line_column::ZeroBased)> {
+        self.fill(Need::Past(span.end.raw())).await?;
+        self.index.span_location(span)
+    }
+
+    /// Get line of offset
+    pub async fn line_of(&mut self, offset: Offset) -> Result<usize> {
+        let offset = offset.raw();
+        self.fill(Need::Past(offset)).await?;
+        if offset >= self.index.len {
+            return Err(self.index.past_eof(offset));
+        }
+        Ok(self.index.lines.search(offset))
+    }
+
+    /// Drain the reader, consume the reader
+    pub async fn drain(&mut self) -> Result<()> {
+        while self.forward().await? > 0 {}
+        Ok(())
+    }
+
+    /// Drain the reader and freeze the line index
+    pub async fn into_index(mut self) -> Result<LineIndex> {
+        self.drain().await?;
+        Ok(self.index)
+    }
+
+    /// Read until `need` is met, the asynchronous counterpart of [`crate::index::Fill::fill`]
+    async fn fill(&mut self, need: Need) -> Result<()> {
+        while self.index.wants(need) && self.forward().await? > 0 {}
+        self.index.check(need)
+    }
+
+    /// Try to get more bytes and update states
+    async fn forward(&mut self) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        let (reader, buffer) = (&mut self.reader, &mut self.buffer);
+        let n = poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, buffer)).await?;
+        if n == 0 {
+            self.eof = true;
+            self.index.finish();
+        } else {
+            self.index.push(&self.buffer[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: AsyncRead + Unpin> From<R> for AsyncStream<R> {
+    fn from(value: R) -> Self {
+        AsyncStream::from_reader(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Error;
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct Unparker(Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_async_stream() {
+        block_on(async {
+            let reader = "ab\r\nc\u{e9}d\r\nef";
+            let mut stream = AsyncStream::new(reader.as_bytes(), 3)
+                .with_line_ending(LineEnding::CrLf)
+                .with_column_unit(ColumnUnit::Char);
+            assert_eq!(stream.line_index(Offset::new(8)).await.unwrap().raw(), (1, 3));
+            assert_eq!(stream.offset_of((1, 2).into()).await.unwrap(), Offset::new(7));
+            assert_eq!(stream.line_of(Offset::new(11)).await.unwrap(), 2);
+            assert!(matches!(
+                stream.offset_of_strict((0, 3).into()).await,
+                Err(Error::ColumnOutOfRange { width: 2, .. })
+            ));
+
+            stream.drain().await.unwrap();
+            let index = stream.into_index().await.unwrap();
+            assert_eq!(index.line_count(), 3);
+        });
+    }
+}
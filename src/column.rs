@@ -0,0 +1,363 @@
+//! Column units and the character tables needed to count them.
+//!
+//! A [`crate::Stream`] throws consumed bytes away, so every non-ASCII character
+//! (and, in grapheme mode, every character extending a grapheme cluster) is
+//! recorded while reading. Bytes which are not part of a valid UTF-8 sequence
+//! count as one column each.
+
+/// Unit in which columns are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColumnUnit {
+    /// Bytes (UTF-8 code units)
+    #[default]
+    Byte,
+    /// Unicode scalar values
+    Char,
+    /// UTF-16 code units
+    Utf16,
+    /// Extended grapheme clusters
+    #[cfg(feature = "grapheme")]
+    Grapheme,
+}
+
+/// A valid UTF-8 sequence longer than one byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MultiByteChar {
+    pub pos: usize,
+    pub len: u8,
+}
+
+/// Character tables recorded while reading
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Chars {
+    unit: ColumnUnit,
+    multibyte: Vec<MultiByteChar>,
+    /// Start offsets of characters which extend the previous grapheme cluster
+    #[cfg(feature = "grapheme")]
+    extends: Vec<usize>,
+    /// Incomplete UTF-8 sequence, which may span two reads
+    pending: Option<Pending>,
+    /// Bytes of the current line, only kept in grapheme mode
+    #[cfg(feature = "grapheme")]
+    line: Vec<u8>,
+    #[cfg(feature = "grapheme")]
+    line_start: usize,
+}
+
+impl Chars {
+    pub fn new(unit: ColumnUnit) -> Self {
+        Self {
+            unit,
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    pub fn unit(&self) -> ColumnUnit {
+        self.unit
+    }
+
+    /// Record characters of `bytes`, which start at `offset`
+    pub fn feed(&mut self, offset: usize, bytes: &[u8]) {
+        if self.unit == ColumnUnit::Byte {
+            return;
+        }
+
+        for (i, &b) in bytes.iter().enumerate() {
+            if let Some(mut pending) = self.pending.take() {
+                if pending.accepts(b) {
+                    pending.seen += 1;
+                    if pending.seen == pending.len {
+                        self.multibyte.push(MultiByteChar {
+                            pos: pending.start,
+                            len: pending.len,
+                        });
+                    } else {
+                        self.pending = Some(pending);
+                    }
+                    continue;
+                }
+            }
+            let len = match b {
+                0xC2..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF4 => 4,
+                _ => continue,
+            };
+            self.pending = Some(Pending {
+                start: offset + i,
+                lead: b,
+                len,
+                seen: 1,
+            });
+        }
+
+        #[cfg(feature = "grapheme")]
+        if self.unit == ColumnUnit::Grapheme {
+            self.feed_line(offset, bytes);
+        }
+    }
+
+    /// All lines before `line_start` are complete.
+    /// Grapheme clusters never span a line break, so complete lines can be segmented.
+    pub fn line_break(&mut self, line_start: usize) {
+        #[cfg(feature = "grapheme")]
+        if self.unit == ColumnUnit::Grapheme && line_start > self.line_start {
+            self.segment(line_start);
+        }
+        #[cfg(not(feature = "grapheme"))]
+        let _ = line_start;
+    }
+
+    /// No more bytes will be fed
+    pub fn finish(&mut self) {
+        self.pending = None;
+        #[cfg(feature = "grapheme")]
+        if self.unit == ColumnUnit::Grapheme {
+            self.segment(self.line_start + self.line.len());
+        }
+    }
+
+    /// Replace the characters of `start..end` with those of `text`, later characters are shifted.
+    /// Characters are found in `text` alone, so both ends must be character boundaries.
+    pub fn edit(&mut self, start: usize, end: usize, text: &[u8]) {
+        if self.unit == ColumnUnit::Byte {
+            return;
+        }
+        let mut inserted = Chars::new(self.unit);
+        inserted.feed(start, text);
+        inserted.finish();
+        let shift = |pos: usize| pos - (end - start) + text.len();
+
+        let lo = self.multibyte.partition_point(|c| c.pos < start);
+        let hi = self.multibyte.partition_point(|c| c.pos < end);
+        for c in &mut self.multibyte[hi..] {
+            c.pos = shift(c.pos);
+        }
+        self.multibyte.splice(lo..hi, inserted.multibyte);
+
+        #[cfg(feature = "grapheme")]
+        {
+            let lo = self.extends.partition_point(|&pos| pos < start);
+            let hi = self.extends.partition_point(|&pos| pos < end);
+            for pos in &mut self.extends[hi..] {
+                *pos = shift(*pos);
+            }
+            self.extends.splice(lo..hi, inserted.extends);
+            if self.unit == ColumnUnit::Grapheme && self.line_start >= end {
+                self.line_start = shift(self.line_start);
+            }
+        }
+    }
+
+    /// Whether no character is recorded, so that every unit is a single byte
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "grapheme")]
+        if !self.extends.is_empty() {
+            return false;
+        }
+        self.multibyte.is_empty()
+    }
+
+    /// Characters of `start..end`, moved to start at 0
+    pub fn slice(&self, start: usize, end: usize) -> Chars {
+        let lo = self.multibyte.partition_point(|c| c.pos < start);
+        let hi = self.multibyte.partition_point(|c| c.pos < end);
+        let multibyte = self.multibyte[lo..hi].iter();
+        Chars {
+            unit: self.unit,
+            multibyte: multibyte.map(|&c| MultiByteChar { pos: c.pos - start, ..c }).collect(),
+            #[cfg(feature = "grapheme")]
+            extends: {
+                let lo = self.extends.partition_point(|&pos| pos < start);
+                let hi = self.extends.partition_point(|&pos| pos < end);
+                self.extends[lo..hi].iter().map(|pos| pos - start).collect()
+            },
+            ..Chars::default()
+        }
+    }
+
+    /// Forget characters before `start`, which must not be inside the current line,
+    /// and move the rest to start at 0
+    pub fn evict(&mut self, start: usize) {
+        let n = self.multibyte.partition_point(|c| c.pos < start);
+        self.multibyte.drain(..n);
+        for c in &mut self.multibyte {
+            c.pos -= start;
+        }
+        if let Some(pending) = &mut self.pending {
+            pending.start -= start;
+        }
+        #[cfg(feature = "grapheme")]
+        {
+            let n = self.extends.partition_point(|&pos| pos < start);
+            self.extends.drain(..n);
+            for pos in &mut self.extends {
+                *pos -= start;
+            }
+            if self.unit == ColumnUnit::Grapheme {
+                self.line_start -= start;
+            }
+        }
+    }
+
+    /// Append characters of `other` moved to start at `offset`, which follows every recorded character
+    pub fn append(&mut self, other: &Chars, offset: usize) {
+        let multibyte = other.multibyte.iter();
+        self.multibyte.extend(multibyte.map(|&c| MultiByteChar { pos: c.pos + offset, ..c }));
+        #[cfg(feature = "grapheme")]
+        {
+            self.extends.extend(other.extends.iter().map(|pos| pos + offset));
+            if self.unit == ColumnUnit::Grapheme {
+                self.line_start = offset + other.line_start;
+            }
+        }
+    }
+
+    /// Column of `offset` in the line `start..end`.
+    /// An offset inside a unit resolves to the column of that unit.
+    pub fn column(&self, start: usize, end: usize, offset: usize) -> usize {
+        if self.unit == ColumnUnit::Byte {
+            return offset - start;
+        }
+        self.units(start, end)
+            .take_while(|&(_, unit_end, _)| unit_end <= offset)
+            .map(|(_, _, width)| width)
+            .sum::<usize>()
+            + offset.saturating_sub(end)
+    }
+
+    /// Count units in `start..end` which end at or before `offset`,
+    /// `start` must be a unit boundary. Returns the count and the end of the last counted unit.
+    pub fn count(&self, start: usize, end: usize, offset: usize) -> (usize, usize) {
+        if self.unit == ColumnUnit::Byte {
+            return (offset - start, offset);
+        }
+        self.units(start, end)
+            .take_while(|&(_, unit_end, _)| unit_end <= offset)
+            .fold((0, start), |(n, _), (_, unit_end, width)| (n + width, unit_end))
+    }
+
+    /// Offset of `column` in the line `start..end`.
+    /// A column inside a unit (e.g. between two UTF-16 surrogates) resolves to the start of that unit,
+    /// a column past the end of the line counts single bytes after `end`.
+    pub fn offset(&self, start: usize, end: usize, column: usize) -> usize {
+        if self.unit == ColumnUnit::Byte {
+            return start + column;
+        }
+        let mut acc = 0;
+        for (pos, _, width) in self.units(start, end) {
+            if acc + width > column {
+                return pos;
+            }
+            acc += width;
+        }
+        end + (column - acc)
+    }
+
+    /// Iterate (start, end, width) of units in `start..end`
+    fn units(&self, start: usize, end: usize) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        let mut chars = self.chars(start, end).peekable();
+        std::iter::from_fn(move || {
+            let (pos, len) = chars.next()?;
+            let (unit_end, width) = match self.unit {
+                ColumnUnit::Byte => (pos + len, len),
+                ColumnUnit::Char => (pos + len, 1),
+                ColumnUnit::Utf16 => (pos + len, 1 + (len == 4) as usize),
+                #[cfg(feature = "grapheme")]
+                ColumnUnit::Grapheme => {
+                    let mut end = pos + len;
+                    while let Some(&(next, len)) = chars.peek() {
+                        if self.extends.binary_search(&next).is_err() {
+                            break;
+                        }
+                        end = next + len;
+                        chars.next();
+                    }
+                    (end, 1)
+                }
+            };
+            Some((pos, unit_end, width))
+        })
+    }
+
+    /// Iterate (start, length) of characters in `start..end`
+    fn chars(&self, start: usize, end: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut i = self.multibyte.partition_point(|c| c.pos < start);
+        let mut pos = start;
+        std::iter::from_fn(move || {
+            if pos >= end {
+                return None;
+            }
+            let len = match self.multibyte.get(i) {
+                Some(c) if c.pos == pos => {
+                    i += 1;
+                    c.len as usize
+                }
+                _ => 1,
+            };
+            let item = (pos, len);
+            pos += len;
+            Some(item)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    start: usize,
+    lead: u8,
+    len: u8,
+    seen: u8,
+}
+
+impl Pending {
+    /// Whether `b` is a valid next byte of this sequence
+    fn accepts(&self, b: u8) -> bool {
+        let range = match (self.seen, self.lead) {
+            (1, 0xE0) => 0xA0..=0xBF,
+            (1, 0xED) => 0x80..=0x9F,
+            (1, 0xF0) => 0x90..=0xBF,
+            (1, 0xF4) => 0x80..=0x8F,
+            _ => 0x80..=0xBF,
+        };
+        range.contains(&b)
+    }
+}
+
+#[cfg(feature = "grapheme")]
+impl Chars {
+    fn feed_line(&mut self, offset: usize, bytes: &[u8]) {
+        if self.line.is_empty() {
+            self.line_start = offset;
+        }
+        self.line.extend_from_slice(bytes);
+    }
+
+    /// Segment the kept bytes before `end`
+    fn segment(&mut self, end: usize) {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let n = end - self.line_start;
+        let mut base = self.line_start;
+        for chunk in self.line[..n].utf8_chunks() {
+            for (i, grapheme) in chunk.valid().grapheme_indices(true) {
+                let extends = grapheme.char_indices().skip(1);
+                self.extends.extend(extends.map(|(j, _)| base + i + j));
+            }
+            base += chunk.valid().len() + chunk.invalid().len();
+        }
+        self.line.drain(..n);
+        self.line_start = end;
+    }
+}
+
+/// Every column unit, for tests which run over all of them
+#[cfg(test)]
+pub(crate) const UNITS: &[ColumnUnit] = &[
+    ColumnUnit::Byte,
+    ColumnUnit::Char,
+    ColumnUnit::Utf16,
+    #[cfg(feature = "grapheme")]
+    ColumnUnit::Grapheme,
+];
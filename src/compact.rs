@@ -0,0 +1,321 @@
+//! Line starts in about half the memory of a `Vec<usize>`.
+//!
+//! Starts are grouped in blocks of [`BLOCK`] lines. Every block keeps the absolute start of
+//! its first line, every line its start relative to that anchor as a `u32`, so a line costs
+//! a little over 4 bytes. A block spanning more than 4 GiB keeps its far starts aside.
+//!
+//! A [`LineIndex`] or [`crate::Stream`] stores its line starts this way after `with_compact_lines`.
+use crate::index::LineIndex;
+use std::mem::size_of;
+
+/// Lines per anchor
+pub const BLOCK: usize = 64;
+
+/// Relative start which does not fit, the absolute start is in `far`
+const FAR: u32 = u32::MAX;
+
+/// Ordered line starts, encoded as `u32` deltas from periodic absolute anchors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactLines {
+    /// Start of the first line of every block
+    anchors: Vec<usize>,
+    /// Start of every line relative to the anchor of its block
+    deltas: Vec<u32>,
+    /// Lines whose relative start is [`FAR`], with their absolute start
+    far: Vec<(usize, usize)>,
+}
+
+impl CompactLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of lines
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Append a line start, which must not be less than the last one
+    pub fn push(&mut self, start: usize) {
+        debug_assert!(self.last().is_none_or(|last| last <= start));
+        let line = self.len();
+        if line.is_multiple_of(BLOCK) {
+            self.anchors.push(start);
+        }
+        let delta = start - self.anchors[line / BLOCK];
+        match u32::try_from(delta) {
+            Ok(delta) if delta != FAR => self.deltas.push(delta),
+            _ => {
+                self.deltas.push(FAR);
+                self.far.push((line, start));
+            }
+        }
+    }
+
+    /// Start of a line
+    pub fn get(&self, line: usize) -> Option<usize> {
+        let delta = *self.deltas.get(line)?;
+        if delta == FAR {
+            let i = self.far.binary_search_by_key(&line, |&(line, _)| line).ok()?;
+            return Some(self.far[i].1);
+        }
+        Some(self.anchors[line / BLOCK] + delta as usize)
+    }
+
+    #[inline]
+    pub fn last(&self) -> Option<usize> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    /// Last line starting at or before `offset`, `None` if every line starts after it.
+    /// Takes O(log n): the block is found among the anchors, then the line inside the block.
+    pub fn search(&self, offset: usize) -> Option<usize> {
+        let block = self.anchors.partition_point(|&anchor| anchor <= offset).checked_sub(1)?;
+        let (mut lo, mut hi) = (block * BLOCK, self.len().min((block + 1) * BLOCK));
+        // Line `lo` starts at or before `offset`, line `hi` after it or does not exist
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            match self.get(mid) {
+                Some(start) if start <= offset => lo = mid,
+                _ => hi = mid,
+            }
+        }
+        Some(lo)
+    }
+
+    /// Line starts in order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).map(|line| self.get(line).unwrap())
+    }
+
+    /// Bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        self.anchors.capacity() * size_of::<usize>()
+            + self.deltas.capacity() * size_of::<u32>()
+            + self.far.capacity() * size_of::<(usize, usize)>()
+    }
+
+    /// Release unused capacity
+    pub fn shrink_to_fit(&mut self) {
+        self.anchors.shrink_to_fit();
+        self.deltas.shrink_to_fit();
+        self.far.shrink_to_fit();
+    }
+}
+
+impl FromIterator<usize> for CompactLines {
+    fn from_iter<I: IntoIterator<Item = usize>>(starts: I) -> Self {
+        let mut lines = CompactLines::new();
+        for start in starts {
+            lines.push(start);
+        }
+        lines.shrink_to_fit();
+        lines
+    }
+}
+
+impl From<&[usize]> for CompactLines {
+    fn from(starts: &[usize]) -> Self {
+        starts.iter().copied().collect()
+    }
+}
+
+/// Line starts of the indexed input, relative to the input rather than the enclosing document
+impl From<&LineIndex> for CompactLines {
+    fn from(index: &LineIndex) -> Self {
+        index.lines.iter().collect()
+    }
+}
+
+/// Line starts of a [`LineIndex`], the first line always starts at 0
+#[derive(Debug, Clone)]
+pub(crate) enum LineStarts {
+    Plain(Vec<usize>),
+    Compact(CompactLines),
+}
+
+impl Default for LineStarts {
+    fn default() -> Self {
+        LineStarts::Plain(vec![0])
+    }
+}
+
+impl LineStarts {
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            LineStarts::Plain(lines) => lines.len(),
+            LineStarts::Compact(lines) => lines.len(),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, line: usize) -> Option<usize> {
+        match self {
+            LineStarts::Plain(lines) => lines.get(line).copied(),
+            LineStarts::Compact(lines) => lines.get(line),
+        }
+    }
+
+    /// Start of a line, which must exist
+    #[inline]
+    pub fn start(&self, line: usize) -> usize {
+        self.get(line).expect("line exists")
+    }
+
+    /// Start of the last line
+    #[inline]
+    pub fn last(&self) -> usize {
+        self.start(self.len() - 1)
+    }
+
+    #[inline]
+    pub fn push(&mut self, start: usize) {
+        match self {
+            LineStarts::Plain(lines) => lines.push(start),
+            LineStarts::Compact(lines) => lines.push(start),
+        }
+    }
+
+    /// Last line starting at or before `offset`
+    pub fn search(&self, offset: usize) -> usize {
+        match self {
+            LineStarts::Plain(lines) => lines.partition_point(|&start| start <= offset) - 1,
+            LineStarts::Compact(lines) => lines.search(offset).unwrap(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + Clone + '_ {
+        (0..self.len()).map(|line| self.start(line))
+    }
+
+    /// Change the starts as a `Vec`, compact starts are decoded and encoded again
+    pub fn modify<T>(&mut self, f: impl FnOnce(&mut Vec<usize>) -> T) -> T {
+        match self {
+            LineStarts::Plain(lines) => f(lines),
+            LineStarts::Compact(lines) => {
+                let mut plain = lines.iter().collect();
+                let result = f(&mut plain);
+                *lines = plain.into_iter().collect();
+                result
+            }
+        }
+    }
+
+    /// Store the starts as [`CompactLines`]
+    pub fn compact(&mut self) {
+        if let LineStarts::Plain(lines) = self {
+            *self = LineStarts::Compact(CompactLines::from(&lines[..]));
+        }
+    }
+
+    /// Bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        match self {
+            LineStarts::Plain(lines) => lines.capacity() * size_of::<usize>(),
+            LineStarts::Compact(lines) => lines.heap_size(),
+        }
+    }
+}
+
+/// Equal if the starts are, however they are stored
+impl PartialEq for LineStarts {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for LineStarts {}
+
+impl Extend<usize> for LineStarts {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, starts: I) {
+        for start in starts {
+            self.push(start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::column::UNITS;
+    use crate::index::test::{EDITED, EDITS};
+    use crate::line_ending::LineEnding;
+    use crate::location::{Offset, Span};
+    use crate::Stream;
+
+    #[test]
+    fn test_compact_lines() {
+        // Lines of varying length, some of them too long for a relative start
+        let mut starts = vec![0];
+        for i in 1..1000usize {
+            let len = match i % 97 {
+                0 => 5 << 30,
+                n => n * 13 % 31,
+            };
+            starts.push(starts[i - 1] + len);
+        }
+        let lines = CompactLines::from(&starts[..]);
+        assert_eq!(lines.len(), starts.len());
+        assert!(lines.iter().eq(starts.iter().copied()));
+        assert_eq!(lines.get(starts.len()), None);
+
+        let last = *starts.last().unwrap();
+        for offset in (0..last + 10).step_by(last / 5000).chain(starts.iter().copied()) {
+            let expected = starts.partition_point(|&start| start <= offset) - 1;
+            assert_eq!(lines.search(offset), Some(expected), "offset {}", offset);
+        }
+        assert_eq!(CompactLines::from(&[3][..]).search(2), None);
+
+        let near: CompactLines = (0..1000).map(|i| i * 40).collect();
+        assert!(near.heap_size() < 1000 * size_of::<usize>() * 6 / 10);
+
+        let text = "a\nbc\n\ndef";
+        let index = Stream::from(text.as_bytes()).into_index().unwrap();
+        let lines = CompactLines::from(&index);
+        assert_eq!(lines.search(7), Some(index.line_of(Offset::new(7)).unwrap()));
+    }
+    #[test]
+    fn test_compact_storage() {
+        let endings = [LineEnding::Lf, LineEnding::Any];
+        for (&unit, ending) in UNITS.iter().flat_map(|u| endings.map(|e| (u, e))) {
+            let stream = || {
+                Stream::new(EDITED.as_bytes(), 3)
+                    .with_column_unit(unit)
+                    .with_line_ending(ending)
+            };
+            let mut plain = stream().into_index().unwrap();
+            let mut compact = stream().with_compact_lines().into_index().unwrap();
+            assert!(matches!(compact.lines, LineStarts::Compact(_)));
+            assert_eq!(compact, plain);
+            for offset in (0..EDITED.len()).map(Offset::new) {
+                assert_eq!(compact.line_index(offset).unwrap(), plain.line_index(offset).unwrap());
+            }
+            for (start, end, inserted) in EDITS {
+                let span = Span::new(Offset::new(start), Offset::new(end));
+                plain.edit(span, inserted.as_bytes()).unwrap();
+                compact.edit(span, inserted.as_bytes()).unwrap();
+                assert_eq!(compact, plain, "{:?} {:?}", unit, ending);
+            }
+        }
+
+        // Evicted lines are dropped from compact starts too
+        let text: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let windowed = Stream::from(text.as_bytes()).with_window_lines(3);
+        let index = windowed.with_compact_lines().into_index().unwrap();
+        assert!(matches!(index.lines, LineStarts::Compact(_)));
+        assert_eq!(index.line_of(Offset::new(text.len() - 1)).unwrap(), 19);
+        assert_eq!(index.line_offset(19), Some(Offset::new(text.len() - 8)));
+
+        let text = "x".repeat(39) + "\n";
+        let plain = Stream::from(text.repeat(1000).as_bytes()).into_index().unwrap();
+        let compact = plain.clone().with_compact_lines();
+        assert!(compact.lines_heap_size() < plain.lines_heap_size() * 6 / 10);
+    }
+}
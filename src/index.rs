@@ -0,0 +1,947 @@
+//! Line index detached from its reader, which can be saved and loaded.
+//!
+//! The binary format (little-endian) is:
+//! ```text
+//! magic      b"SLCINDEX"
+//! version    u8
+//! ending     u8          line ending policy
+//! unit       u8          column unit
+//! len        u64         length of the indexed input
+//! fingerprint u64
+//! base       u64 * 3     offset, line and column of the input in its document
+//! lines      varint      number of lines
+//! starts     varint*     deltas between line starts, the first line start (0) is implied
+//! breaks     u8*         line break lengths of every line but the last, unless the policy is LF
+//! ```
+use crate::column::{Chars, ColumnUnit};
+use crate::compact::LineStarts;
+use crate::line_ending::{LineEnding, Scanner};
+use crate::location::{line_column, Offset, Span};
+use crate::stream::{Error, Result};
+use std::io;
+
+const MAGIC: &[u8; 8] = b"SLCINDEX";
+const VERSION: u8 = 2;
+
+/// Line-start table of an input, with everything needed to convert offsets.
+///
+/// A [`crate::Stream`] grows its index while reading, [`crate::Stream::into_index`] freezes it.
+/// A frozen index needs no reader, so it is `Send + Sync` and can be shared behind an `Arc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    pub(crate) lines: LineStarts,
+    /// Length of the line break ending each line, empty for [`LineEnding::Lf`]
+    pub(crate) breaks: Vec<u8>,
+    pub(crate) chars: Chars,
+    pub(crate) scanner: Scanner,
+    /// Length of the indexed input
+    pub(crate) len: usize,
+    pub(crate) base: Base,
+}
+
+/// Where the indexed input starts in its enclosing document.
+/// The base column only applies to the first line.
+/// Lines evicted from the front of the index keep their place in the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Base {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    /// Bytes evicted from the front
+    pub evicted: usize,
+    /// Lines evicted from the front
+    pub evicted_lines: usize,
+    /// Bytes evicted but still stored in front of the kept ones
+    pub stale: usize,
+    /// Lines evicted but still stored in front of the kept ones
+    pub stale_lines: usize,
+}
+
+impl Base {
+    /// Offset in the input of an offset in the enclosing document
+    pub fn local_offset(&self, offset: Offset) -> Result<usize> {
+        let offset = offset.raw();
+        let input = offset
+            .checked_sub(self.offset)
+            .ok_or(Error::OffsetBeforeBase {
+                offset,
+                base: self.offset,
+            })?;
+        let local = input.checked_sub(self.evicted).filter(|&local| local >= self.stale);
+        local.ok_or(Error::OffsetEvicted {
+            offset,
+            start: self.offset + self.evicted + self.stale,
+        })
+    }
+
+    /// Line in the input of a line in the enclosing document
+    pub fn local_line(&self, line: usize) -> Result<usize> {
+        let input = line
+            .checked_sub(self.line)
+            .ok_or(Error::LocationBeforeBase { line, column: 0 })?;
+        let local = input.checked_sub(self.evicted_lines);
+        let local = local.filter(|&local| local >= self.stale_lines);
+        local.ok_or(Error::LineEvicted {
+            line,
+            first: self.line + self.evicted_lines + self.stale_lines,
+        })
+    }
+
+    /// Line and column in the input of a location in the enclosing document
+    pub fn local_location(&self, location: line_column::ZeroBased) -> Result<(usize, usize)> {
+        let (line, column) = location.raw();
+        let local = self.local_line(line)?;
+        if local > 0 || self.evicted_lines > 0 {
+            return Ok((local, column));
+        }
+        match column.checked_sub(self.column) {
+            Some(column) => Ok((0, column)),
+            None => Err(Error::LocationBeforeBase { line, column }),
+        }
+    }
+
+    #[inline]
+    pub fn global_offset(&self, offset: usize) -> Offset {
+        Offset::new(self.offset + self.evicted + offset)
+    }
+
+    #[inline]
+    pub fn global_line(&self, line: usize) -> usize {
+        self.line + self.evicted_lines + line
+    }
+
+    pub fn global_location(&self, line: usize, column: usize) -> line_column::ZeroBased {
+        match line {
+            0 if self.evicted_lines == 0 => (self.line, self.column + column).into(),
+            _ => (self.global_line(line), column).into(),
+        }
+    }
+
+    /// `offset` of an input of `len` bytes is past its end
+    pub fn past_eof(&self, offset: usize, len: usize) -> Error {
+        Error::OffsetPastEof {
+            offset: self.global_offset(offset).raw(),
+            len: self.global_offset(len).raw(),
+        }
+    }
+
+    /// `line` of an input of `lines` lines does not exist
+    pub fn line_out_of_range(&self, line: usize, lines: usize) -> Error {
+        Error::LineOutOfRange {
+            line: self.global_line(line),
+            lines: self.global_line(lines),
+        }
+    }
+}
+
+/// Bytes a lookup needs indexed, lines and offsets are local to the input
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Need {
+    /// A line starting after the offset, or EOF, so that the line of the offset is complete
+    Past(usize),
+    /// The end of the line, which must exist
+    Line(usize),
+    /// Enough of the line to count columns on it: its start for bytes, its end for other units
+    Column(usize),
+}
+
+impl Need {
+    /// The same need once `bytes` bytes and `lines` lines are freed from the front
+    pub(crate) fn shifted(self, bytes: usize, lines: usize) -> Self {
+        match self {
+            Need::Past(offset) => Need::Past(offset - bytes),
+            Need::Line(line) => Need::Line(line - lines),
+            Need::Column(line) => Need::Column(line - lines),
+        }
+    }
+}
+
+/// A stream indexing its input as lookups need it
+pub(crate) trait Fill {
+    fn index(&self) -> &LineIndex;
+
+    /// Push the next bytes of the input into the index, returns their length, 0 at EOF
+    fn fill_more(&mut self) -> Result<usize>;
+
+    /// Index until `need` is met, fails if the input ends first
+    fn fill(&mut self, need: Need) -> Result<()> {
+        while self.index().wants(need) && self.fill_more()? > 0 {}
+        self.index().check(need)
+    }
+}
+
+impl Default for LineIndex {
+    fn default() -> Self {
+        Self::new(LineEnding::default(), ColumnUnit::default())
+    }
+}
+
+impl LineIndex {
+    pub(crate) fn new(ending: LineEnding, unit: ColumnUnit) -> Self {
+        Self {
+            lines: LineStarts::default(),
+            breaks: Vec::new(),
+            chars: Chars::new(unit),
+            scanner: Scanner::new(ending),
+            len: 0,
+            base: Base::default(),
+        }
+    }
+
+    /// Index `bytes`, which directly follow the indexed input
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        let ending = self.scanner.ending();
+        let (lines, breaks) = (&mut self.lines, &mut self.breaks);
+        let found = |start, len| {
+            lines.push(start); // next line begin
+            if ending != LineEnding::Lf {
+                breaks.push(len);
+            }
+        };
+        self.scanner.scan(self.len, bytes, found);
+        self.chars.feed(self.len, bytes);
+        self.chars.line_break(self.lines.last());
+        self.len += bytes.len();
+    }
+
+    /// The whole input has been pushed
+    pub(crate) fn finish(&mut self) {
+        let ending = self.scanner.ending();
+        let (lines, breaks) = (&mut self.lines, &mut self.breaks);
+        self.scanner.finish(self.len, |start, len| {
+            lines.push(start);
+            if ending != LineEnding::Lf {
+                breaks.push(len);
+            }
+        });
+        self.chars.finish();
+    }
+
+    /// Count columns in `unit`
+    ///
+    /// # Panics
+    /// Panics if any byte has already been pushed.
+    pub(crate) fn set_column_unit(&mut self, unit: ColumnUnit) {
+        assert_eq!(self.len, 0, "column unit must be set before reading");
+        self.chars = Chars::new(unit);
+    }
+
+    /// Terminate lines by `ending`
+    ///
+    /// # Panics
+    /// Panics if any byte has already been pushed.
+    pub(crate) fn set_line_ending(&mut self, ending: LineEnding) {
+        assert_eq!(self.len, 0, "line ending must be set before reading");
+        self.scanner = Scanner::new(ending);
+    }
+
+    /// Whether more bytes must be pushed to meet `need`
+    pub(crate) fn wants(&self, need: Need) -> bool {
+        match need {
+            Need::Past(offset) => self.lines.last() <= offset,
+            Need::Line(line) => line + 1 >= self.lines.len(),
+            // Byte columns do not need the whole line
+            Need::Column(line) if self.column_unit() == ColumnUnit::Byte => {
+                line >= self.lines.len()
+            }
+            Need::Column(line) => self.wants(Need::Line(line)),
+        }
+    }
+
+    /// First line a lookup with `need` reads
+    pub(crate) fn needed_line(&self, need: Need) -> usize {
+        match need {
+            Need::Past(offset) => self.lines.search(offset),
+            Need::Line(line) | Need::Column(line) => line,
+        }
+    }
+
+    /// Fails if `need` cannot be met, once it is met or the input has ended
+    pub(crate) fn check(&self, need: Need) -> Result<()> {
+        match need {
+            Need::Past(offset) if offset > self.len => Err(self.past_eof(offset)),
+            Need::Line(line) | Need::Column(line) if line >= self.lines.len() => {
+                Err(self.line_out_of_range(line))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Length of the indexed input
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.scanner.ending()
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.chars.unit()
+    }
+
+    /// Offset of the first indexed byte in the enclosing document
+    #[inline]
+    pub fn base(&self) -> Offset {
+        Offset::new(self.base.offset)
+    }
+
+    /// Location of the first indexed byte in the enclosing document
+    #[inline]
+    pub fn base_location(&self) -> line_column::ZeroBased {
+        (self.base.line, self.base.column).into()
+    }
+
+    /// Report offsets and locations relative to an enclosing document,
+    /// in which the indexed input starts at `offset` and `location`.
+    pub fn with_base(mut self, offset: Offset, location: line_column::ZeroBased) -> Self {
+        let (line, column) = location.raw();
+        self.base = Base {
+            offset: offset.raw(),
+            line,
+            column,
+            ..self.base
+        };
+        self
+    }
+
+    /// Store line starts as [`crate::CompactLines`], in about half the memory.
+    /// Lookups stay O(log n), but an [`Self::edit`] re-encodes every line start.
+    pub fn with_compact_lines(mut self) -> Self {
+        self.lines.compact();
+        self
+    }
+
+    /// Bytes allocated for line starts
+    #[inline]
+    pub fn lines_heap_size(&self) -> usize {
+        self.lines.heap_size()
+    }
+
+    #[inline]
+    pub fn line_offset(&self, line: usize) -> Option<Offset> {
+        let line = self.local_line(line).ok()?;
+        self.lines.get(line).map(|start| self.global_offset(start))
+    }
+
+    /// Get line of offset
+    pub fn line_of(&self, offset: Offset) -> Result<usize> {
+        let offset = self.local_offset(offset)?;
+        if offset >= self.len {
+            return Err(self.past_eof(offset));
+        }
+        Ok(self.base.global_line(self.lines.search(offset)))
+    }
+
+    /// Get line and column number from offset
+    pub fn line_index(&self, offset: Offset) -> Result<line_column::ZeroBased> {
+        let offset = self.local_offset(offset)?;
+        if offset >= self.len {
+            return Err(self.past_eof(offset));
+        }
+        let line = self.lines.search(offset);
+        Ok(self.global_location(line, self.column(line, offset)))
+    }
+
+    /// Get start and end line-column locations of a span, the end is exclusive and may be EOF
+    pub fn span_location(
+        &self,
+        span: Span,
+    ) -> Result<(line_column::ZeroBased, line_column::ZeroBased)> {
+        span.check()?;
+        let (start, end) = (self.local_offset(span.start)?, self.local_offset(span.end)?);
+        if end > self.len {
+            return Err(self.past_eof(end));
+        }
+        let start_line = self.lines.search(start);
+        let end_line = self.search_from(end, start_line);
+        Ok((
+            self.global_location(start_line, self.column(start_line, start)),
+            self.global_location(end_line, self.column(end_line, end)),
+        ))
+    }
+
+    /// Get offset from line and column number, the column is not checked
+    pub fn offset_of(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, col) = self.local_location(line_index)?;
+        let Some(start) = self.lines.get(line) else {
+            return Err(self.line_out_of_range(line));
+        };
+        let offset = match self.column_unit() {
+            ColumnUnit::Byte => start + col,
+            _ => self.chars.offset(start, self.line_end(line), col),
+        };
+        Ok(self.global_offset(offset))
+    }
+
+    /// Get offset from line and column number,
+    /// fails with [`Error::ColumnOutOfRange`] if the column exceeds the line length (excluding the line break)
+    pub fn offset_of_strict(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let local = self.local_line(line)?;
+        let width = self.line_width(line).ok_or_else(|| self.line_out_of_range(local))?;
+        if column > width {
+            return Err(Error::ColumnOutOfRange {
+                line,
+                column,
+                width,
+            });
+        }
+        self.offset_of(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub fn offset_of_clamped(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let local = self.local_line(line)?;
+        let width = self.line_width(line).ok_or_else(|| self.line_out_of_range(local))?;
+        self.offset_of((line, column.min(width)).into())
+    }
+
+    /// Width of a line in [`Self::column_unit`], excluding its line break.
+    /// The first line includes the base column.
+    pub fn line_width(&self, line: usize) -> Option<usize> {
+        let line = self.local_line(line).ok()?;
+        let start = self.lines.get(line)?;
+        let end = self.content_end(line);
+        Some(self.global_location(line, self.chars.column(start, end, end)).column)
+    }
+
+    /// Replace the bytes of `span` with `text`, without rescanning the rest of the input.
+    ///
+    /// Only `text` is scanned, later line starts and characters are shifted in place,
+    /// so an edit still takes time linear in the lines and non-ASCII characters after it;
+    /// [`crate::RopeIndex`] edits in O(log n).
+    /// Line breaks and characters are found in `text` alone, so the edit must not split
+    /// a character or a `\r\n` pair, nor put a `\n` right after a `\r`;
+    /// extend the span over such neighbours.
+    pub fn edit(&mut self, span: Span, text: &[u8]) -> Result<()> {
+        span.check()?;
+        let (start, end) = (self.local_offset(span.start)?, self.local_offset(span.end)?);
+        if end > self.len {
+            return Err(self.past_eof(end));
+        }
+
+        let mut inserted = LineIndex::new(self.line_ending(), ColumnUnit::Byte);
+        inserted.scanner.scan(start, text, |line, len| {
+            inserted.lines.push(line);
+            inserted.breaks.push(len);
+        });
+        // A trailing lone `\r` is a line break, the next byte is no `\n`
+        inserted.scanner.finish(start + text.len(), |line, len| {
+            inserted.lines.push(line);
+            inserted.breaks.push(len);
+        });
+        let shift = |pos: usize| pos - (end - start) + text.len();
+
+        // Lines starting in `start + 1..=end` are ended by removed line breaks
+        let lo = self.lines.search(start) + 1;
+        let hi = self.lines.search(end) + 1;
+        self.lines.modify(|lines| {
+            for line in &mut lines[hi..] {
+                *line = shift(*line);
+            }
+            lines.splice(lo..hi, inserted.lines.iter().skip(1));
+        });
+        if self.line_ending() != LineEnding::Lf {
+            self.breaks.splice(lo - 1..hi - 1, inserted.breaks);
+        }
+        self.chars.edit(start, end, text);
+        self.len = shift(self.len);
+        Ok(())
+    }
+
+    /// Line of an indexed offset, searching forward from line `from`, which starts before it
+    pub(crate) fn search_from(&self, offset: usize, from: usize) -> usize {
+        let LineStarts::Plain(lines) = &self.lines else {
+            return self.lines.search(offset);
+        };
+        let lines = &lines[from..];
+        // Gallop first, nearby lines are the common case
+        let mut step = 1;
+        while step < lines.len() && lines[step] <= offset {
+            step *= 2;
+        }
+        let lo = step / 2;
+        let hi = lines.len().min(step);
+        from + lo + lines[lo..hi].partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Column of `offset`, which lies on `line`
+    pub(crate) fn column(&self, line: usize, offset: usize) -> usize {
+        let start = self.lines.start(line);
+        match self.column_unit() {
+            ColumnUnit::Byte => offset - start,
+            _ => self.chars.column(start, self.line_end(line), offset),
+        }
+    }
+
+    /// Length of the line break ending a complete line
+    #[inline]
+    fn break_len(&self, line: usize) -> usize {
+        self.breaks.get(line).map_or(1, |&n| n as usize)
+    }
+
+    /// End offset of a line, excluding the line break
+    pub(crate) fn content_end(&self, line: usize) -> usize {
+        match self.lines.get(line + 1) {
+            Some(next) => next - self.break_len(line),
+            None => self.len,
+        }
+    }
+
+    /// End offset of a line, including the line break
+    pub(crate) fn line_end(&self, line: usize) -> usize {
+        self.lines.get(line + 1).unwrap_or(self.len)
+    }
+
+    /// Evict the lines before `first`, later lines keep their place in the document.
+    /// Evicted lines are only freed once they outnumber the kept ones, as freeing shifts
+    /// every kept line, so that evicting takes amortized constant time per line.
+    /// Returns the number of bytes and lines freed, by which local offsets and lines shift.
+    pub(crate) fn evict(&mut self, first: usize) -> (usize, usize) {
+        self.base.stale = self.lines.start(first);
+        self.base.stale_lines = first;
+        if first < self.lines.len() - first {
+            return (0, 0);
+        }
+        self.free()
+    }
+
+    /// Free the evicted lines, returns the number of bytes and lines freed
+    pub(crate) fn free(&mut self) -> (usize, usize) {
+        let (start, lines) = (self.base.stale, self.base.stale_lines);
+        if lines == 0 {
+            return (0, 0);
+        }
+        self.lines.modify(|starts| {
+            starts.drain(..lines);
+            for line in starts {
+                *line -= start;
+            }
+        });
+        if self.line_ending() != LineEnding::Lf {
+            self.breaks.drain(..lines);
+        }
+        self.chars.evict(start);
+        self.len -= start;
+        self.base.evicted += start;
+        self.base.evicted_lines += lines;
+        self.base.stale = 0;
+        self.base.stale_lines = 0;
+        (start, lines)
+    }
+
+    #[inline]
+    pub(crate) fn local_offset(&self, offset: Offset) -> Result<usize> {
+        self.base.local_offset(offset)
+    }
+
+    #[inline]
+    pub(crate) fn local_line(&self, line: usize) -> Result<usize> {
+        self.base.local_line(line)
+    }
+
+    #[inline]
+    pub(crate) fn local_location(
+        &self,
+        location: line_column::ZeroBased,
+    ) -> Result<(usize, usize)> {
+        self.base.local_location(location)
+    }
+
+    #[inline]
+    pub(crate) fn global_offset(&self, offset: usize) -> Offset {
+        self.base.global_offset(offset)
+    }
+
+    #[inline]
+    pub(crate) fn global_location(&self, line: usize, column: usize) -> line_column::ZeroBased {
+        self.base.global_location(line, column)
+    }
+
+    #[inline]
+    pub(crate) fn past_eof(&self, offset: usize) -> Error {
+        self.base.past_eof(offset, self.len)
+    }
+
+    #[inline]
+    pub(crate) fn line_out_of_range(&self, line: usize) -> Error {
+        self.base.line_out_of_range(line, self.lines.len())
+    }
+
+    /// Write the index of `source`, which starts at its current position.
+    /// Character tables are not saved, [`Self::load`] rebuilds them from the source.
+    /// The source is sampled for a fingerprint, its position is restored.
+    /// Fails with [`Error::LineEvicted`] once lines are evicted by a window,
+    /// as the index no longer covers the whole source.
+    pub fn save<W, S>(&self, mut writer: W, source: &mut S) -> Result<()>
+    where
+        W: io::Write,
+        S: io::Read + io::Seek,
+    {
+        let evicted = self.base.evicted_lines + self.base.stale_lines;
+        if evicted > 0 {
+            return Err(Error::LineEvicted {
+                line: self.base.line,
+                first: self.base.line + evicted,
+            });
+        }
+        let fingerprint = fingerprint(source, self.len)?;
+
+        writer.write_all(MAGIC)?;
+        let (ending, unit) = (self.line_ending(), self.column_unit());
+        writer.write_all(&[VERSION, encode_ending(ending), encode_unit(unit)])?;
+        writer.write_all(&(self.len as u64).to_le_bytes())?;
+        writer.write_all(&fingerprint.to_le_bytes())?;
+        for n in [self.base.offset, self.base.line, self.base.column] {
+            writer.write_all(&(n as u64).to_le_bytes())?;
+        }
+        write_varint(&mut writer, self.lines.len() as u64)?;
+        let starts = self.lines.iter();
+        for (start, next) in starts.clone().zip(starts.skip(1)) {
+            write_varint(&mut writer, (next - start) as u64)?;
+        }
+        writer.write_all(&self.breaks)?;
+        Ok(())
+    }
+
+    /// Read an index saved by [`Self::save`] for `source`, which starts at its current position.
+    /// Columns not counted in bytes need the characters of the source, which is then read once.
+    /// Fails with [`Error::StaleIndex`] if the source no longer matches the index.
+    pub fn load<I, S>(mut index: I, source: &mut S) -> Result<Self>
+    where
+        I: io::Read,
+        S: io::Read + io::Seek,
+    {
+        let mut header = [0; 11];
+        index.read_exact(&mut header)?;
+        if &header[..8] != MAGIC || header[8] != VERSION {
+            return Err(invalid("unknown magic or version"));
+        }
+        let ending = decode_ending(header[9]).ok_or_else(|| invalid("unknown line ending"))?;
+        let unit = decode_unit(header[10]).ok_or_else(|| invalid("unknown column unit"))?;
+        let len = read_u64(&mut index)? as usize;
+        let expected = read_u64(&mut index)?;
+        let base = Base {
+            offset: read_u64(&mut index)? as usize,
+            line: read_u64(&mut index)? as usize,
+            column: read_u64(&mut index)? as usize,
+            ..Base::default()
+        };
+
+        let count = read_varint(&mut index)? as usize;
+        if count == 0 {
+            return Err(invalid("no lines"));
+        }
+        // Every line but the first starts after a line break
+        if count - 1 > len {
+            return Err(invalid("more lines than bytes"));
+        }
+        // The length is not checked against the source yet, so do not trust it for allocation
+        let mut lines = Vec::with_capacity(count.min(1 << 16));
+        lines.push(0);
+        for _ in 1..count {
+            let delta = read_varint(&mut index)? as usize;
+            let start = usize::checked_add(*lines.last().unwrap(), delta);
+            let start = start.filter(|&start| start <= len);
+            let start = start.ok_or_else(|| invalid("line start exceeds length"))?;
+            lines.push(start);
+        }
+        let mut breaks = vec![];
+        if ending != LineEnding::Lf {
+            breaks = vec![0; count - 1];
+            index.read_exact(&mut breaks)?;
+        }
+
+        if fingerprint(source, len)? != expected {
+            return Err(Error::StaleIndex);
+        }
+        let mut chars = Chars::new(unit);
+        if unit != ColumnUnit::Byte {
+            scan_chars(&mut chars, source, len, &lines)?;
+        }
+        Ok(Self {
+            lines: LineStarts::Plain(lines),
+            breaks,
+            chars,
+            len,
+            base,
+            ..Self::new(ending, unit)
+        })
+    }
+}
+
+/// Feed the first `len` bytes of `source`, whose lines start at `lines`, to `chars`.
+/// The position of `source` is restored.
+fn scan_chars<S>(chars: &mut Chars, source: &mut S, len: usize, lines: &[usize]) -> io::Result<()>
+where
+    S: io::Read + io::Seek,
+{
+    let origin = source.stream_position()?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    while offset < len {
+        let n = buf.len().min(len - offset);
+        source.read_exact(&mut buf[..n])?;
+        chars.feed(offset, &buf[..n]);
+        offset += n;
+        // Lines starting by the end of this block had their break fed entirely
+        chars.line_break(lines[lines.partition_point(|&start| start <= offset) - 1]);
+    }
+    chars.finish();
+    source.seek(io::SeekFrom::Start(origin))?;
+    Ok(())
+}
+
+/// Hash `len` and samples of `source` from its current position, which is restored.
+/// Inputs up to 64 KiB are hashed entirely, larger ones by 16 evenly spaced 4 KiB blocks,
+/// so a change between the samples which keeps the length goes unnoticed.
+fn fingerprint<S: io::Read + io::Seek>(source: &mut S, len: usize) -> io::Result<u64> {
+    const BLOCK: usize = 4 * 1024;
+    const SAMPLES: usize = 16;
+
+    let origin = source.stream_position()?;
+    let end = source.seek(io::SeekFrom::End(0))?;
+    let actual = end.saturating_sub(origin) as usize;
+
+    let mut hash = Fnv::default();
+    hash.write(&(actual as u64).to_le_bytes());
+    if actual == len {
+        let mut block = vec![0; BLOCK * SAMPLES];
+        let samples = if len <= block.len() {
+            vec![(0, len)]
+        } else {
+            let last = len - BLOCK;
+            (0..SAMPLES).map(|i| (i * last / (SAMPLES - 1), BLOCK)).collect()
+        };
+        for (start, n) in samples {
+            source.seek(io::SeekFrom::Start(origin + start as u64))?;
+            source.read_exact(&mut block[..n])?;
+            hash.write(&block[..n]);
+        }
+    }
+    source.seek(io::SeekFrom::Start(origin))?;
+    Ok(hash.0)
+}
+
+/// 64-bit FNV-1a, stable across platforms and versions
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+fn encode_ending(ending: LineEnding) -> u8 {
+    match ending {
+        LineEnding::Lf => 0,
+        LineEnding::CrLf => 1,
+        LineEnding::Any => 2,
+        LineEnding::Unicode => 3,
+    }
+}
+
+fn decode_ending(n: u8) -> Option<LineEnding> {
+    Some(match n {
+        0 => LineEnding::Lf,
+        1 => LineEnding::CrLf,
+        2 => LineEnding::Any,
+        3 => LineEnding::Unicode,
+        _ => return None,
+    })
+}
+
+fn encode_unit(unit: ColumnUnit) -> u8 {
+    match unit {
+        ColumnUnit::Byte => 0,
+        ColumnUnit::Char => 1,
+        ColumnUnit::Utf16 => 2,
+        #[cfg(feature = "grapheme")]
+        ColumnUnit::Grapheme => 3,
+    }
+}
+
+fn decode_unit(n: u8) -> Option<ColumnUnit> {
+    Some(match n {
+        0 => ColumnUnit::Byte,
+        1 => ColumnUnit::Char,
+        2 => ColumnUnit::Utf16,
+        #[cfg(feature = "grapheme")]
+        3 => ColumnUnit::Grapheme,
+        _ => return None,
+    })
+}
+
+fn write_varint<W: io::Write>(writer: &mut W, mut n: u64) -> io::Result<()> {
+    let mut buf = [0; 10];
+    let mut i = 0;
+    while n >= 0x80 {
+        buf[i] = n as u8 | 0x80;
+        n >>= 7;
+        i += 1;
+    }
+    buf[i] = n as u8;
+    writer.write_all(&buf[..=i])
+}
+
+fn read_varint<R: io::Read>(reader: &mut R) -> Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let mut b = [0];
+        reader.read_exact(&mut b)?;
+        n |= ((b[0] & 0x7f) as u64) << shift;
+        if b[0] < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn read_u64<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::InvalidIndex(msg)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::column::UNITS;
+    use crate::Stream;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_save_load() {
+        let text: String = (0..20_000).map(|i| format!("line {}\r\n", i)).collect();
+        let mut stream = Stream::from(Cursor::new(text.clone())).with_line_ending(LineEnding::CrLf);
+        let mut saved = vec![];
+        stream.save_index(&mut saved).unwrap();
+
+        let mut loaded = Stream::load_index(Cursor::new(text.clone()), saved.as_slice()).unwrap();
+        assert_eq!(loaded.line_count(), 20_001);
+        assert_eq!(loaded.line_index(Offset::new(12)).unwrap().raw(), (1, 4));
+        assert_eq!(loaded.fetch_line_text(7).unwrap(), "line 7");
+
+        let index = LineIndex::load(saved.as_slice(), &mut Cursor::new(text.clone())).unwrap();
+        assert_eq!(index.offset_of((2, 1).into()).unwrap(), Offset::new(17));
+        assert_eq!(index.line_of(Offset::new(index.len() - 1)).unwrap(), 19_999);
+
+        let mut changed = text.clone().into_bytes();
+        changed[text.len() - 3] = b'x';
+        let err = LineIndex::load(saved.as_slice(), &mut Cursor::new(changed));
+        assert!(matches!(err, Err(Error::StaleIndex)));
+        let err = LineIndex::load(saved.as_slice(), &mut Cursor::new(&text[1..]));
+        assert!(matches!(err, Err(Error::StaleIndex)));
+        let err = LineIndex::load(&saved[1..], &mut Cursor::new(text.clone()));
+        assert!(matches!(err, Err(Error::InvalidIndex(_))));
+
+        // A huge line count right after the 51-byte header
+        let mut corrupt = saved[..51].to_vec();
+        corrupt.extend_from_slice(&[0xff; 8]);
+        corrupt.push(0x3f);
+        let err = LineIndex::load(corrupt.as_slice(), &mut Cursor::new(text.clone()));
+        assert!(matches!(err, Err(Error::InvalidIndex("more lines than bytes"))));
+        // A line start overflowing `usize`
+        let mut corrupt = saved[..51].to_vec();
+        corrupt.extend_from_slice(&[3, 1]);
+        corrupt.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        let err = LineIndex::load(corrupt.as_slice(), &mut Cursor::new(text));
+        assert!(matches!(err, Err(Error::InvalidIndex("line start exceeds length"))));
+    }
+
+    #[test]
+    fn test_save_load_unit() {
+        let text: String = (0..3_000).map(|i| format!("\u{3bb}\u{1f600} {}\n", i)).collect();
+        for &unit in UNITS {
+            let mut stream = Stream::from(Cursor::new(text.clone())).with_column_unit(unit);
+            stream.set_base_location(Offset::new(100), (10, 5).into());
+            let mut saved = vec![];
+            stream.save_index(&mut saved).unwrap();
+
+            let mut loaded = Stream::load_index(Cursor::new(text.clone()), saved.as_slice());
+            let loaded = loaded.as_mut().unwrap();
+            assert_eq!(loaded.column_unit(), unit);
+            for offset in [100, 107, 112, 30_000, 99 + text.len()] {
+                let offset = Offset::new(offset);
+                let expected = stream.line_index(offset).unwrap();
+                assert_eq!(loaded.line_index(offset).unwrap(), expected, "{:?}", unit);
+                let offset = stream.offset_of(expected.clone()).unwrap();
+                assert_eq!(loaded.offset_of(expected).unwrap(), offset, "{:?}", unit);
+            }
+        }
+    }
+
+    /// Text edited by [`EDITS`]
+    pub(crate) const EDITED: &str = "fn f() {\r\n    \u{3bb}\r\n}\n\u{1f600}\r";
+
+    /// Edits applied in order: start, end and inserted text
+    pub(crate) const EDITS: [(usize, usize, &str); 7] = [
+        (2, 2, " g"),
+        (12, 20, "x\ry\r\n\u{e9}"),
+        (0, 9, ""),
+        (6, 6, "\n\n"),
+        (0, 0, "\r\n"),
+        (20, 21, ""),
+        (3, 16, "\u{1f600}\n"),
+    ];
+
+    #[test]
+    fn test_edit() {
+        let endings = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Any];
+        for (&unit, ending) in UNITS.iter().flat_map(|u| endings.map(|e| (u, e))) {
+            let build = |text: &str| {
+                let stream = Stream::new(text.as_bytes(), 3)
+                    .with_column_unit(unit)
+                    .with_line_ending(ending);
+                stream.into_index().unwrap()
+            };
+            let mut text = EDITED.to_string();
+            let mut index = build(&text);
+            for (start, end, inserted) in EDITS {
+                let span = Span::new(Offset::new(start), Offset::new(end));
+                index.edit(span, inserted.as_bytes()).unwrap();
+                text.replace_range(start..end, inserted);
+                let expected = build(&text);
+                assert_eq!(index, expected, "{:?} {:?} {:?}", unit, ending, text);
+            }
+        }
+
+        let mut index = LineIndex::default();
+        let span = Span::new(Offset::new(0), Offset::new(1));
+        assert!(matches!(index.edit(span, b""), Err(Error::OffsetPastEof { .. })));
+        let (start, end) = (Offset::new(1), Offset::new(0));
+        let reversed = index.edit(Span { start, end }, b"");
+        assert!(matches!(reversed, Err(Error::InvalidSpan { start: 1, end: 0 })));
+        let reversed = index.span_location(Span { start, end });
+        assert!(matches!(reversed, Err(Error::InvalidSpan { start: 1, end: 0 })));
+    }
+}
@@ -1,4 +1,29 @@
+#[cfg(feature = "async")]
+pub mod async_stream;
+pub mod column;
+pub mod compact;
+pub mod index;
+pub mod line_ending;
 pub mod location;
+pub mod lsp;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+pub mod parallel;
+mod retain;
+pub mod rope;
+pub mod source_map;
+pub mod sparse;
 pub mod stream;
+pub mod visual;
 
+#[cfg(feature = "async")]
+pub use async_stream::AsyncStream;
+pub use compact::CompactLines;
+pub use index::LineIndex;
+#[cfg(feature = "mmap")]
+pub use mapped::MappedStream;
+pub use parallel::ParallelIndexer;
+pub use rope::RopeIndex;
+pub use source_map::SourceMap;
+pub use sparse::SparseStream;
 pub use stream::Stream;
@@ -0,0 +1,204 @@
+//! Line terminator policies.
+
+/// Which byte sequences terminate a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LineEnding {
+    /// `\n` only, a `\r` before it is part of the line
+    #[default]
+    Lf,
+    /// `\n` and `\r\n`, a lone `\r` is part of the line
+    CrLf,
+    /// `\n`, `\r\n` and a lone `\r`
+    Any,
+    /// Like [`LineEnding::Any`], plus NEL (U+0085), LS (U+2028) and PS (U+2029)
+    Unicode,
+}
+
+impl LineEnding {
+    /// Whether a `\r` not followed by `\n` terminates a line
+    #[inline]
+    fn lone_cr(self) -> bool {
+        matches!(self, LineEnding::Any | LineEnding::Unicode)
+    }
+}
+
+/// Finds line breaks in chunks of bytes, a line break may span two chunks
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Scanner {
+    ending: LineEnding,
+    /// Last two bytes of previous chunks
+    prev: [u8; 2],
+}
+
+impl Scanner {
+    pub fn new(ending: LineEnding) -> Self {
+        Self {
+            ending,
+            prev: [0; 2],
+        }
+    }
+
+    #[inline]
+    pub fn ending(&self) -> LineEnding {
+        self.ending
+    }
+
+    /// Scan `bytes` which start at `offset`.
+    /// Calls `found(next_line_start, break_len)` for every line break, in order.
+    ///
+    /// Only bytes which may end a line break are visited, they are searched a word at a time.
+    pub fn scan(&mut self, offset: usize, bytes: &[u8], mut found: impl FnMut(usize, u8)) {
+        let ending = self.ending;
+        let needles: &[u8] = match ending {
+            // `\r` only matters before `\n`
+            LineEnding::Lf | LineEnding::CrLf => b"\n",
+            LineEnding::Any => b"\n\r",
+            LineEnding::Unicode => b"\n\r\x85\xa8\xa9",
+        };
+        let prev = self.prev;
+        // Byte at `pos`, which may belong to the previous chunks
+        let byte_at = |pos: usize| match pos.checked_sub(2) {
+            Some(i) => bytes[i],
+            None => prev[pos],
+        };
+
+        // A `\r` ending the previous chunk is decided by the first byte
+        if ending.lone_cr() && prev[1] == b'\r' && bytes.first().is_some_and(|&b| b != b'\n') {
+            found(offset, 1);
+        }
+        let mut i = 0;
+        while let Some(n) = find_any(&bytes[i..], needles) {
+            let pos = i + n;
+            // Position in `prev` followed by `bytes`
+            let at = pos + 2;
+            match bytes[pos] {
+                b'\n' => {
+                    let cr = ending != LineEnding::Lf && byte_at(at - 1) == b'\r';
+                    found(offset + pos + 1, 1 + cr as u8)
+                }
+                b'\r' => match bytes.get(pos + 1) {
+                    Some(&next) if next != b'\n' => found(offset + pos + 1, 1),
+                    // Decided by `\n` or the next chunk
+                    _ => {}
+                },
+                0x85 if byte_at(at - 1) == 0xC2 => found(offset + pos + 1, 2),
+                0xA8 | 0xA9 if [byte_at(at - 2), byte_at(at - 1)] == [0xE2, 0x80] => {
+                    found(offset + pos + 1, 3)
+                }
+                _ => {}
+            }
+            i = pos + 1;
+        }
+
+        self.prev = match bytes {
+            [] => prev,
+            [b] => [prev[1], *b],
+            [.., a, b] => [*a, *b],
+        };
+    }
+
+    /// No more bytes will be scanned, `end` is the length of the input
+    pub fn finish(&mut self, end: usize, mut found: impl FnMut(usize, u8)) {
+        if self.ending.lone_cr() && self.prev[1] == b'\r' {
+            found(end, 1);
+        }
+        self.prev = [0; 2];
+    }
+}
+
+const WORD: usize = std::mem::size_of::<usize>();
+/// `0x01` in every byte
+const LO: usize = usize::MAX / 0xFF;
+/// `0x80` in every byte
+const HI: usize = LO << 7;
+
+/// Position of the first byte of `bytes` which is one of `needles`.
+/// Aligned words are tested at once, the unaligned ends byte by byte.
+fn find_any(bytes: &[u8], needles: &[u8]) -> Option<usize> {
+    let head = bytes.as_ptr().align_offset(WORD).min(bytes.len());
+    if let Some(i) = find_any_scalar(&bytes[..head], needles) {
+        return Some(i);
+    }
+    let mut pos = head;
+    while pos + WORD <= bytes.len() {
+        let word = usize::from_ne_bytes(bytes[pos..pos + WORD].try_into().unwrap());
+        let hit = needles.iter().fold(0, |hit, &n| hit | has_zero(word ^ (LO * n as usize)));
+        if hit != 0 {
+            break;
+        }
+        pos += WORD;
+    }
+    find_any_scalar(&bytes[pos..], needles).map(|i| pos + i)
+}
+
+/// Non-zero if any byte of `x` is zero
+#[inline]
+fn has_zero(x: usize) -> usize {
+    x.wrapping_sub(LO) & !x & HI
+}
+
+/// Byte-at-a-time fallback of [`find_any`]
+#[inline]
+fn find_any_scalar(bytes: &[u8], needles: &[u8]) -> Option<usize> {
+    bytes.iter().position(|b| needles.contains(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn breaks(ending: LineEnding, text: &str, chunk: usize) -> Vec<(usize, u8)> {
+        let mut scanner = Scanner::new(ending);
+        let mut found = vec![];
+        for (i, bytes) in text.as_bytes().chunks(chunk).enumerate() {
+            scanner.scan(i * chunk, bytes, |start, len| found.push((start, len)));
+        }
+        scanner.finish(text.len(), |start, len| found.push((start, len)));
+        found
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let text = "a\r\nb\rc\nd\u{85}e\u{2028}f\r";
+        let expected = [
+            (LineEnding::Lf, vec![(3, 1), (7, 1)]),
+            (LineEnding::CrLf, vec![(3, 2), (7, 1)]),
+            (LineEnding::Any, vec![(3, 2), (5, 1), (7, 1), (16, 1)]),
+            (
+                LineEnding::Unicode,
+                vec![(3, 2), (5, 1), (7, 1), (10, 2), (14, 3), (16, 1)],
+            ),
+        ];
+        for (ending, expected) in expected {
+            for chunk in 1..=text.len() {
+                assert_eq!(breaks(ending, text, chunk), expected, "{:?} {}", ending, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_any() {
+        let mut bytes = [b'x'; 100];
+        for (i, b) in [(3, b'\n'), (21, b'\r'), (40, 0x85), (77, 0xA9)] {
+            bytes[i] = b;
+        }
+        for needles in [&b"\n"[..], b"\n\r", b"\n\r\x85\xa8\xa9", b"\xa9"] {
+            for start in 0..bytes.len() {
+                let bytes = &bytes[start..];
+                assert_eq!(find_any(bytes, needles), find_any_scalar(bytes, needles));
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_chunks() {
+        // Longer than a word between line breaks, one byte chunks never search words
+        let text = "a\r\nb\rc\nd\u{85}e\u{2028}f\r".repeat(3) + &"0123456789\r".repeat(4);
+        for ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Any, LineEnding::Unicode] {
+            let expected = breaks(ending, &text, 1);
+            for chunk in 2..=text.len() {
+                assert_eq!(breaks(ending, &text, chunk), expected, "{:?} {}", ending, chunk);
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use crate::stream::{Error, Result};
+use std::ops::Range;
+
 /// Zero-based offset of bytes
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct Offset(usize);
 
 impl Offset {
@@ -18,6 +21,77 @@ impl From<usize> for Offset {
     }
 }
 
+/// Half-open range of offsets `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub start: Offset,
+    pub end: Offset,
+}
+
+impl Span {
+    /// # Panics
+    /// Panics if `start > end`.
+    pub fn new(start: Offset, end: Offset) -> Self {
+        assert!(start <= end, "span start {:?} > end {:?}", start, end);
+        Self { start, end }
+    }
+
+    /// Length in bytes, 0 if the span starts after its end
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.end.raw().saturating_sub(self.start.raw())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether `offset` lies in this span
+    #[inline]
+    pub fn contains(&self, offset: Offset) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    /// Fail if the span starts after its end, which its public fields allow
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.start > self.end {
+            return Err(Error::InvalidSpan {
+                start: self.start.raw(),
+                end: self.end.raw(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Smallest span covering both spans
+    pub fn merge(&self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// Overlapping part of both spans, `None` if they share no byte
+    pub fn intersect(&self, other: Span) -> Option<Span> {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        (start < end).then_some(Span { start, end })
+    }
+}
+
+impl From<Range<Offset>> for Span {
+    fn from(range: Range<Offset>) -> Self {
+        Span::new(range.start, range.end)
+    }
+}
+
+impl From<Span> for Range<Offset> {
+    fn from(span: Span) -> Self {
+        span.start..span.end
+    }
+}
+
 pub mod line_column {
     use std::num::NonZeroUsize;
 
@@ -87,3 +161,23 @@ pub mod line_column {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_span() {
+        let span = |start, end| Span::new(Offset::new(start), Offset::new(end));
+        let a = span(2, 6);
+        assert_eq!(a.len(), 4);
+        assert!(a.contains(Offset::new(2)) && !a.contains(Offset::new(6)));
+        assert_eq!(a.merge(span(8, 9)), span(2, 9));
+        assert_eq!(a.intersect(span(4, 9)), Some(span(4, 6)));
+        assert_eq!(a.intersect(span(6, 9)), None);
+        assert_eq!(a.intersect(span(7, 9)), None);
+
+        let (start, end) = (Offset::new(6), Offset::new(2));
+        assert_eq!(Span { start, end }.len(), 0);
+    }
+}
@@ -0,0 +1,227 @@
+//! Conversion between offsets and [LSP](https://microsoft.github.io/language-server-protocol/) positions.
+//!
+//! The negotiated [`PositionEncoding`] decides the column unit of the stream:
+//! ```
+//! use stream_locate_converter::{lsp, Stream};
+//!
+//! let encoding = lsp::PositionEncoding::negotiate(["utf-16"]);
+//! let mut stream = Stream::from("a\u{1f600}b".as_bytes()).with_column_unit(encoding.column_unit());
+//! let position = lsp::position(&mut stream, 5.into()).unwrap();
+//! assert_eq!(position, lsp::Position::new(0, 3));
+//! ```
+use crate::column::ColumnUnit;
+use crate::location::Offset;
+use crate::stream::{Error, Result, Stream};
+use std::io;
+use std::ops;
+use std::str::FromStr;
+
+/// Encoding of [`Position::character`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PositionEncoding {
+    /// UTF-8 code units (bytes)
+    Utf8,
+    /// UTF-16 code units, mandatory for every server
+    #[default]
+    Utf16,
+    /// UTF-32 code units (Unicode scalar values)
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Pick the first encoding offered by the client, falling back to UTF-16
+    pub fn negotiate<I, S>(client: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        client
+            .into_iter()
+            .find_map(|kind| kind.as_ref().parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Value of `PositionEncodingKind`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16 => "utf-16",
+            Self::Utf32 => "utf-32",
+        }
+    }
+
+    /// Column unit the stream has to count in
+    pub fn column_unit(&self) -> ColumnUnit {
+        match self {
+            Self::Utf8 => ColumnUnit::Byte,
+            Self::Utf16 => ColumnUnit::Utf16,
+            Self::Utf32 => ColumnUnit::Char,
+        }
+    }
+}
+
+impl FromStr for PositionEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "utf-8" => Ok(Self::Utf8),
+            "utf-16" => Ok(Self::Utf16),
+            "utf-32" => Ok(Self::Utf32),
+            _ => Err(Error::UnknownPositionEncoding(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<ColumnUnit> for PositionEncoding {
+    type Error = Error;
+
+    fn try_from(unit: ColumnUnit) -> Result<Self> {
+        match unit {
+            ColumnUnit::Byte => Ok(Self::Utf8),
+            ColumnUnit::Utf16 => Ok(Self::Utf16),
+            ColumnUnit::Char => Ok(Self::Utf32),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::UnsupportedColumnUnit(unit)),
+        }
+    }
+}
+
+/// Zero-based LSP position, `character` is counted in the negotiated [`PositionEncoding`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+impl Position {
+    pub fn new(line: u32, character: u32) -> Self {
+        Self { line, character }
+    }
+}
+
+/// LSP range, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+}
+
+/// Get LSP position of offset.
+/// An offset inside a line break resolves to the end of the line,
+/// the end of the document is a valid position.
+pub fn position<R: io::Read>(stream: &mut Stream<R>, offset: Offset) -> Result<Position> {
+    PositionEncoding::try_from(stream.column_unit())?;
+    let (line, column) = match stream.line_index(offset) {
+        Ok(line_index) => line_index.raw(),
+        Err(Error::OffsetPastEof { offset, len }) if offset == len => {
+            (stream.base_location().line + stream.line_count() - 1, usize::MAX)
+        }
+        Err(err) => return Err(err),
+    };
+    let width = stream.line_width(line)?.unwrap_or(column);
+    Ok(Position {
+        line: to_u32(line)?,
+        character: to_u32(column.min(width))?,
+    })
+}
+
+/// Get offset of LSP position.
+///
+/// As the specification requires, a character past the end of a line resolves to the end of that line.
+/// A line past the end of the document resolves to the end of the document.
+pub fn offset<R: io::Read>(stream: &mut Stream<R>, position: Position) -> Result<Offset> {
+    PositionEncoding::try_from(stream.column_unit())?;
+    let line = position.line as usize;
+    match stream.line_width(line)? {
+        Some(width) => {
+            let column = (position.character as usize).min(width);
+            stream.offset_of((line, column).into())
+        }
+        None => Ok(Offset::new(stream.base() + stream.read_len())),
+    }
+}
+
+/// Get LSP range of an offset range
+pub fn range<R: io::Read>(stream: &mut Stream<R>, range: ops::Range<Offset>) -> Result<Range> {
+    Ok(Range {
+        start: position(stream, range.start)?,
+        end: position(stream, range.end)?,
+    })
+}
+
+/// Get offset range of an LSP range
+pub fn offset_range<R: io::Read>(
+    stream: &mut Stream<R>,
+    range: Range,
+) -> Result<ops::Range<Offset>> {
+    Ok(offset(stream, range.start)?..offset(stream, range.end)?)
+}
+
+fn to_u32(n: usize) -> Result<u32> {
+    u32::try_from(n).map_err(|_| Error::PositionOverflow(n))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stream(text: &str, encoding: PositionEncoding) -> Stream<&[u8]> {
+        Stream::from(text.as_bytes()).with_column_unit(encoding.column_unit())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(PositionEncoding::negotiate(["utf-32", "utf-8"]), PositionEncoding::Utf32);
+        assert_eq!(PositionEncoding::negotiate(["ucs-2"]), PositionEncoding::Utf16);
+        assert_eq!(PositionEncoding::negotiate(Vec::<String>::new()), PositionEncoding::Utf16);
+        let unknown = "ucs-2".parse::<PositionEncoding>();
+        assert!(matches!(unknown, Err(Error::UnknownPositionEncoding(e)) if e == "ucs-2"));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let text = "\u{1f600}a\nb";
+        let expected = [
+            (PositionEncoding::Utf8, Position::new(0, 5)),
+            (PositionEncoding::Utf16, Position::new(0, 3)),
+            (PositionEncoding::Utf32, Position::new(0, 2)),
+        ];
+        for (encoding, pos) in expected {
+            let mut stream = stream(text, encoding);
+            assert_eq!(position(&mut stream, Offset::new(5)).unwrap(), pos);
+            assert_eq!(offset(&mut stream, pos).unwrap(), Offset::new(5));
+        }
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut stream = stream("ab\ncd", PositionEncoding::Utf16);
+        assert_eq!(offset(&mut stream, Position::new(0, 9)).unwrap(), Offset::new(2));
+        assert_eq!(offset(&mut stream, Position::new(7, 0)).unwrap(), Offset::new(5));
+
+        let range = range(&mut stream, Offset::new(1)..Offset::new(4)).unwrap();
+        assert_eq!(range, Range::new(Position::new(0, 1), Position::new(1, 1)));
+        let offsets = offset_range(&mut stream, range).unwrap();
+        assert_eq!(offsets, Offset::new(1)..Offset::new(4));
+
+        assert_eq!(position(&mut stream, Offset::new(5)).unwrap(), Position::new(1, 2));
+        assert!(position(&mut stream, Offset::new(6)).is_err());
+    }
+
+    #[test]
+    fn test_base() {
+        // Lines before the base are not in the stream
+        let mut stream = stream("ab\ncd", PositionEncoding::Utf16);
+        stream.set_base_location(Offset::new(100), (5, 0).into());
+        assert_eq!(offset(&mut stream, Position::new(6, 1)).unwrap(), Offset::new(104));
+        let before = offset(&mut stream, Position::new(2, 0));
+        assert!(matches!(before, Err(Error::LocationBeforeBase { line: 2, .. })));
+    }
+}
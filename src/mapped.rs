@@ -0,0 +1,240 @@
+//! Stream over bytes already in memory, such as a memory-mapped file.
+use crate::column::ColumnUnit;
+use crate::index::{Fill, LineIndex, Need};
+use crate::line_ending::LineEnding;
+use crate::location::{line_column, Offset, Span};
+use crate::stream::Result;
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// A stream over in-memory bytes, by default a memory-mapped file.
+///
+/// The bytes are indexed in place as lookups need them, nothing is copied,
+/// and line text is borrowed from the mapping.
+#[derive(Debug)]
+pub struct MappedStream<Bytes = Mmap> {
+    bytes: Bytes,
+    index: LineIndex,
+    eof: bool,
+}
+
+impl MappedStream {
+    /// Map a file and stream over it.
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated while it is mapped, see [`Mmap::map`].
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(Self::new(Mmap::map(&file)?))
+    }
+}
+
+impl<B: AsRef<[u8]>> MappedStream<B> {
+    /// Bytes indexed per lookup step
+    const CHUNK_SIZE: usize = 1 << 20;
+
+    pub fn new(bytes: B) -> Self {
+        Self {
+            bytes,
+            index: LineIndex::default(),
+            eof: false,
+        }
+    }
+
+    /// All bytes, indexed or not
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes.as_ref()
+    }
+
+    #[inline]
+    pub fn line_offset(&self, line: usize) -> Option<Offset> {
+        self.index.line_offset(line)
+    }
+
+    /// Number of lines indexed so far
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.index.line_count()
+    }
+
+    /// Indexed length
+    #[inline]
+    pub fn read_len(&self) -> usize {
+        self.index.len
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.index.column_unit()
+    }
+
+    /// Set the unit in which columns are counted.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been indexed.
+    pub fn with_column_unit(mut self, unit: ColumnUnit) -> Self {
+        self.index.set_column_unit(unit);
+        self
+    }
+
+    /// Which byte sequences terminate a line
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.index.line_ending()
+    }
+
+    /// Set which byte sequences terminate a line.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been indexed.
+    pub fn with_line_ending(mut self, ending: LineEnding) -> Self {
+        self.index.set_line_ending(ending);
+        self
+    }
+
+    /// Get offset from line and column number, the column is counted in [`Self::column_unit`]
+    pub fn offset_of(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Column(line_index.line))?;
+        self.index.offset_of(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// fails with [`crate::stream::Error::ColumnOutOfRange`] if the column exceeds the line length
+    pub fn offset_of_strict(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Line(line_index.line))?;
+        self.index.offset_of_strict(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub fn offset_of_clamped(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.fill(Need::Line(line_index.line))?;
+        self.index.offset_of_clamped(line_index)
+    }
+
+    /// Get line and column number from offset, the column is counted in [`Self::column_unit`]
+    pub fn line_index(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        self.fill(Need::Past(offset.raw()))?;
+        self.index.line_index(offset)
+    }
+
+    /// Get start and end line-column locations of a span, the end is exclusive and may be EOF
+    pub fn span_location(
+        &mut self,
+        span: Span,
+    ) -> Result<(line_column::ZeroBased, line_column::ZeroBased)> {
+        self.fill(Need::Past(span.end.raw()))?;
+        self.index.span_location(span)
+    }
+
+    /// Get line of offset
+    pub fn line_of(&mut self, offset: Offset) -> Result<usize> {
+        self.fill(Need::Past(offset.raw()))?;
+        self.index.line_of(offset)
+    }
+
+    /// Get bytes of a line, excluding its line break, borrowed from the mapping
+    pub fn line_bytes(&mut self, line: usize) -> Result<&[u8]> {
+        self.fill(Need::Line(line))?;
+        let (start, end) = (self.index.lines.start(line), self.index.content_end(line));
+        Ok(&self.bytes()[start..end])
+    }
+
+    /// Get text of a line, excluding its line break.
+    /// Invalid UTF-8 is replaced with `U+FFFD`, valid text is borrowed from the mapping.
+    pub fn line_text(&mut self, line: usize) -> Result<Cow<'_, str>> {
+        Ok(String::from_utf8_lossy(self.line_bytes(line)?))
+    }
+
+    /// Get bytes of a span, borrowed from the mapping
+    pub fn slice(&mut self, span: Span) -> Result<&[u8]> {
+        span.check()?;
+        self.fill(Need::Past(span.end.raw()))?;
+        Ok(&self.bytes()[span.start.raw()..span.end.raw()])
+    }
+
+    /// Index all bytes
+    pub fn drain(&mut self) {
+        while self.forward() > 0 {}
+    }
+
+    /// Index all bytes and freeze the line index
+    pub fn into_index(mut self) -> LineIndex {
+        self.drain();
+        self.index
+    }
+
+    /// Index the next chunk, returns its length
+    fn forward(&mut self) -> usize {
+        if self.eof {
+            return 0;
+        }
+        let bytes = self.bytes.as_ref();
+        let start = self.index.len;
+        let end = bytes.len().min(start + Self::CHUNK_SIZE);
+        if start == end {
+            self.eof = true;
+            self.index.finish();
+            return 0;
+        }
+        self.index.push(&bytes[start..end]);
+        end - start
+    }
+}
+
+impl<B: AsRef<[u8]>> Fill for MappedStream<B> {
+    #[inline]
+    fn index(&self) -> &LineIndex {
+        &self.index
+    }
+
+    #[inline]
+    fn fill_more(&mut self) -> Result<usize> {
+        Ok(self.forward())
+    }
+}
+
+impl<B: AsRef<[u8]>> From<B> for MappedStream<B> {
+    fn from(bytes: B) -> Self {
+        MappedStream::new(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Error;
+
+    #[test]
+    fn test_mapped_stream() {
+        let text = "ab\r\nc\u{e9}d\r\nef";
+        let mut stream = MappedStream::new(text.as_bytes())
+            .with_line_ending(LineEnding::CrLf)
+            .with_column_unit(ColumnUnit::Char);
+        assert_eq!(stream.line_index(Offset::new(8)).unwrap().raw(), (1, 3));
+        assert_eq!(stream.offset_of((1, 2).into()).unwrap(), Offset::new(7));
+        assert_eq!(stream.line_text(1).unwrap(), "c\u{e9}d");
+        assert!(matches!(stream.line_text(1).unwrap(), Cow::Borrowed(_)));
+        let span = Span::new(Offset::new(1), Offset::new(5));
+        assert_eq!(stream.slice(span).unwrap(), b"b\r\nc");
+        let (start, end) = (Offset::new(7), Offset::new(1));
+        assert!(matches!(stream.slice(Span { start, end }), Err(Error::InvalidSpan { .. })));
+        assert!(matches!(stream.line_bytes(3), Err(Error::LineOutOfRange { .. })));
+        assert_eq!(stream.into_index().line_count(), 3);
+    }
+
+    #[test]
+    fn test_from_path() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let mut stream = unsafe { MappedStream::from_path(path) }.unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        let first = text.lines().next().unwrap();
+        assert_eq!(stream.line_text(0).unwrap(), first);
+        assert_eq!(stream.offset_of((1, 0).into()).unwrap(), Offset::new(first.len() + 1));
+    }
+}
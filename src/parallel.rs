@@ -0,0 +1,133 @@
+//! Line indexing of in-memory inputs on many threads.
+use crate::column::ColumnUnit;
+use crate::index::LineIndex;
+use crate::line_ending::LineEnding;
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Builds the [`LineIndex`] of in-memory bytes (e.g. a [`crate::MappedStream`]) on many threads.
+///
+/// The input is split into one chunk per thread, each boundary is moved just past a `\n`,
+/// where no line break, character or grapheme cluster can span it under any [`LineEnding`].
+/// Chunks are indexed independently, then their tables are shifted and concatenated.
+/// An input without `\n` is indexed on one thread.
+#[derive(Debug, Clone)]
+pub struct ParallelIndexer {
+    threads: usize,
+    ending: LineEnding,
+    unit: ColumnUnit,
+}
+
+impl Default for ParallelIndexer {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            ending: LineEnding::default(),
+            unit: ColumnUnit::default(),
+        }
+    }
+}
+
+impl ParallelIndexer {
+    /// Use all available cores
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of threads, at least one
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Which byte sequences terminate a line
+    pub fn with_line_ending(mut self, ending: LineEnding) -> Self {
+        self.ending = ending;
+        self
+    }
+
+    /// Unit in which columns are counted
+    pub fn with_column_unit(mut self, unit: ColumnUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Index `bytes`, the result equals the index of a [`crate::Stream`] drained over them
+    pub fn build(&self, bytes: &[u8]) -> LineIndex {
+        let chunks = self.split(bytes);
+        let indexes: Vec<LineIndex> = thread::scope(|scope| {
+            let workers: Vec<_> = chunks
+                .windows(2)
+                .map(|w| {
+                    let chunk = &bytes[w[0]..w[1]];
+                    scope.spawn(move || {
+                        let mut index = LineIndex::new(self.ending, self.unit);
+                        index.push(chunk);
+                        index.finish();
+                        index
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        let mut index = LineIndex::new(self.ending, self.unit);
+        for (chunk, start) in indexes.iter().zip(chunks) {
+            // The first line start of a chunk is the last one of the previous chunk
+            index.lines.extend(chunk.lines.iter().skip(1).map(|line| start + line));
+            index.breaks.extend_from_slice(&chunk.breaks);
+            index.chars.append(&chunk.chars, start);
+        }
+        index.len = bytes.len();
+        index
+    }
+
+    /// Chunk boundaries, from 0 to the length of `bytes`
+    fn split(&self, bytes: &[u8]) -> Vec<usize> {
+        let size = bytes.len().div_ceil(self.threads);
+        let mut bounds = vec![0];
+        let mut start = 0;
+        while start < bytes.len() {
+            let nominal = (start + size).min(bytes.len());
+            start = match bytes[nominal..].iter().position(|&b| b == b'\n') {
+                Some(i) if nominal + i + 1 < bytes.len() => nominal + i + 1,
+                _ => bytes.len(),
+            };
+            bounds.push(start);
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::column::UNITS;
+    use crate::location::Offset;
+    use crate::Stream;
+
+    #[test]
+    fn test_parallel_index() {
+        let text = "a\r\n\u{e9}\u{301}\rb\u{2028}c\n".repeat(50) + "\u{1f600}\r";
+        let endings = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Any, LineEnding::Unicode];
+        for (&unit, ending) in UNITS.iter().flat_map(|u| endings.map(|e| (u, e))) {
+            let stream = Stream::new(text.as_bytes(), 7)
+                .with_column_unit(unit)
+                .with_line_ending(ending);
+            let expected = stream.into_index().unwrap();
+            for threads in [1, 2, 3, 16, 1000] {
+                let index = ParallelIndexer::new()
+                    .with_threads(threads)
+                    .with_line_ending(ending)
+                    .with_column_unit(unit)
+                    .build(text.as_bytes());
+                assert_eq!(index, expected, "{:?} {:?} {}", unit, ending, threads);
+            }
+        }
+
+        let index = ParallelIndexer::new().build(b"");
+        assert_eq!(index.line_count(), 1);
+        let index = ParallelIndexer::new().with_threads(4).build(b"ab\ncd\nef\ngh\n");
+        assert_eq!(index.line_index(Offset::new(10)).unwrap().raw(), (3, 1));
+    }
+}
@@ -0,0 +1,93 @@
+//! Bytes kept in memory while reading.
+use std::borrow::Cow;
+
+/// Read bytes, kept in fixed-size chunks so that appending never moves them
+#[derive(Debug, Default)]
+pub(crate) struct Retained {
+    chunks: Vec<Vec<u8>>,
+    len: usize,
+    limit: usize,
+}
+
+impl Retained {
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    pub fn new(limit: usize) -> Self {
+        Self {
+            chunks: Vec::new(),
+            len: 0,
+            limit,
+        }
+    }
+
+    /// Number of bytes kept
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Keep `bytes` until the limit is reached
+    pub fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(self.limit - self.len);
+        let mut rest = &bytes[..n];
+        while !rest.is_empty() {
+            let chunk = match self.chunks.last_mut() {
+                Some(chunk) if chunk.len() < Self::CHUNK_SIZE => chunk,
+                _ => {
+                    self.chunks.push(Vec::with_capacity(Self::CHUNK_SIZE));
+                    self.chunks.last_mut().unwrap()
+                }
+            };
+            let m = rest.len().min(Self::CHUNK_SIZE - chunk.len());
+            chunk.extend_from_slice(&rest[..m]);
+            rest = &rest[m..];
+        }
+        self.len += n;
+    }
+
+    /// Get bytes of `start..end`, which must be kept
+    pub fn slice(&self, start: usize, end: usize) -> Cow<'_, [u8]> {
+        debug_assert!(start <= end && end <= self.len);
+        if start == end {
+            return Cow::Borrowed(&[]);
+        }
+        let (first, last) = (start / Self::CHUNK_SIZE, (end - 1) / Self::CHUNK_SIZE);
+        let from = start % Self::CHUNK_SIZE;
+        if first == last {
+            let to = from + (end - start);
+            return Cow::Borrowed(&self.chunks[first][from..to]);
+        }
+
+        let mut bytes = Vec::with_capacity(end - start);
+        let mut pos = start;
+        while pos < end {
+            let chunk = &self.chunks[pos / Self::CHUNK_SIZE];
+            let from = pos % Self::CHUNK_SIZE;
+            let to = chunk.len().min(from + (end - pos));
+            bytes.extend_from_slice(&chunk[from..to]);
+            pos += to - from;
+        }
+        Cow::Owned(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slice_across_chunks() {
+        let bytes: Vec<u8> = (0..3 * Retained::CHUNK_SIZE).map(|i| i as u8).collect();
+        let mut retained = Retained::new(bytes.len() - 10);
+        for chunk in bytes.chunks(1000) {
+            retained.push(chunk);
+        }
+        assert_eq!(retained.len(), bytes.len() - 10);
+
+        let size = Retained::CHUNK_SIZE;
+        for (start, end) in [(0, 0), (5, size), (size - 3, 2 * size + 7), (size, 2 * size)] {
+            assert_eq!(retained.slice(start, end).as_ref(), &bytes[start..end]);
+        }
+        assert!(matches!(retained.slice(10, size), Cow::Borrowed(_)));
+    }
+}
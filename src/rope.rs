@@ -0,0 +1,510 @@
+//! Line table in a balanced tree, for large inputs which are edited often.
+//!
+//! Lines are kept in a treap ordered by position. Every node knows the number of lines and bytes
+//! of its subtree, so line starts are never stored and an edit does not shift them.
+//! Looking up an offset or a line takes O(log n) for n lines, an edit O(log n) plus
+//! the lines it removes or inserts.
+use crate::column::{Chars, ColumnUnit};
+use crate::index::{Base, LineIndex};
+use crate::line_ending::{LineEnding, Scanner};
+use crate::location::{line_column, Offset, Span};
+use crate::stream::{Error, Result};
+
+/// A line including its line break
+#[derive(Debug, Clone)]
+struct Line {
+    len: usize,
+    break_len: u8,
+    /// Characters relative to the line start, `None` if every unit is a single byte
+    chars: Option<Box<Chars>>,
+}
+
+impl Line {
+    fn new(len: usize, break_len: u8, chars: Chars) -> Self {
+        Self {
+            len,
+            break_len,
+            chars: (!chars.is_empty()).then(|| Box::new(chars)),
+        }
+    }
+
+    /// Column of `offset` relative to the line start
+    fn column(&self, offset: usize) -> usize {
+        match &self.chars {
+            Some(chars) => chars.column(0, self.len, offset),
+            None => offset,
+        }
+    }
+
+    /// Offset of `column` relative to the line start
+    fn offset(&self, column: usize) -> usize {
+        match &self.chars {
+            Some(chars) => chars.offset(0, self.len, column),
+            None => column,
+        }
+    }
+
+    /// Width in columns, excluding the line break
+    fn width(&self) -> usize {
+        let end = self.len - self.break_len as usize;
+        match &self.chars {
+            Some(chars) => chars.column(0, end, end),
+            None => end,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    line: Line,
+    priority: u64,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// Lines of this subtree
+    lines: usize,
+    /// Bytes of this subtree
+    bytes: usize,
+}
+
+/// Line table backed by a balanced tree, with the conversion API of [`LineIndex`].
+///
+/// [`LineIndex::edit`] shifts every later line start, [`Self::edit`] only rebalances the tree.
+/// Offsets and locations are relative to the base of the index it was built from.
+#[derive(Debug, Clone)]
+pub struct RopeIndex {
+    nodes: Vec<Node>,
+    /// Slots of removed nodes
+    free: Vec<usize>,
+    root: Option<usize>,
+    unit: ColumnUnit,
+    ending: LineEnding,
+    base: Base,
+    /// State of the priority generator
+    seed: u64,
+}
+
+impl From<&LineIndex> for RopeIndex {
+    fn from(index: &LineIndex) -> Self {
+        let mut rope = Self {
+            nodes: Vec::with_capacity(index.line_count()),
+            free: Vec::new(),
+            root: None,
+            unit: index.column_unit(),
+            ending: index.line_ending(),
+            base: index.base,
+            seed: 0x9e37_79b9_7f4a_7c15,
+        };
+        let lines = (0..index.line_count()).map(|line| {
+            let (start, end) = (index.lines.start(line), index.line_end(line));
+            let break_len = (end - index.content_end(line)) as u8;
+            Line::new(end - start, break_len, index.chars.slice(start, end))
+        });
+        rope.root = rope.build(lines);
+        rope
+    }
+}
+
+impl RopeIndex {
+    /// Length of the indexed input
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes_of(self.root)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.lines_of(self.root)
+    }
+
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.ending
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.unit
+    }
+
+    pub fn line_offset(&self, line: usize) -> Option<Offset> {
+        let (start, _) = self.nth(self.base.local_line(line).ok()?)?;
+        Some(self.base.global_offset(start))
+    }
+
+    /// Get line of offset
+    pub fn line_of(&self, offset: Offset) -> Result<usize> {
+        let offset = self.base.local_offset(offset)?;
+        if offset >= self.len() {
+            return Err(self.base.past_eof(offset, self.len()));
+        }
+        let (line, _, _) = self.find(offset);
+        Ok(self.base.global_line(line))
+    }
+
+    /// Get line and column number from offset
+    pub fn line_index(&self, offset: Offset) -> Result<line_column::ZeroBased> {
+        let offset = self.base.local_offset(offset)?;
+        if offset >= self.len() {
+            return Err(self.base.past_eof(offset, self.len()));
+        }
+        Ok(self.location(offset))
+    }
+
+    /// Get start and end line-column locations of a span, the end is exclusive and may be EOF
+    pub fn span_location(
+        &self,
+        span: Span,
+    ) -> Result<(line_column::ZeroBased, line_column::ZeroBased)> {
+        span.check()?;
+        let start = self.base.local_offset(span.start)?;
+        let end = self.base.local_offset(span.end)?;
+        if end > self.len() {
+            return Err(self.base.past_eof(end, self.len()));
+        }
+        Ok((self.location(start), self.location(end)))
+    }
+
+    /// Get offset from line and column number, the column is not checked
+    pub fn offset_of(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = self.base.local_location(line_index)?;
+        let Some((start, node)) = self.nth(line) else {
+            return Err(self.base.line_out_of_range(line, self.line_count()));
+        };
+        Ok(self.base.global_offset(start + self.nodes[node].line.offset(column)))
+    }
+
+    /// Get offset from line and column number,
+    /// fails with [`Error::ColumnOutOfRange`] if the column exceeds the line length (excluding the line break)
+    pub fn offset_of_strict(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let width = self.checked_width(line)?;
+        if column > width {
+            return Err(Error::ColumnOutOfRange {
+                line,
+                column,
+                width,
+            });
+        }
+        self.offset_of(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub fn offset_of_clamped(&self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let width = self.checked_width(line)?;
+        self.offset_of((line, column.min(width)).into())
+    }
+
+    /// Width of a line in [`Self::column_unit`], excluding its line break.
+    /// The first line includes the base column.
+    pub fn line_width(&self, line: usize) -> Option<usize> {
+        let line = self.base.local_line(line).ok()?;
+        let (_, node) = self.nth(line)?;
+        Some(self.base.global_location(line, self.nodes[node].line.width()).column)
+    }
+
+    /// Replace the bytes of `span` with `text`, only the lines touched by the edit are rebuilt.
+    /// See [`LineIndex::edit`] for what the edit must not split.
+    pub fn edit(&mut self, span: Span, text: &[u8]) -> Result<()> {
+        span.check()?;
+        let start = self.base.local_offset(span.start)?;
+        let end = self.base.local_offset(span.end)?;
+        if end > self.len() {
+            return Err(self.base.past_eof(end, self.len()));
+        }
+        let (first, first_start, a) = self.find(start);
+        let (last, last_start, b) = self.find(end);
+        let (a, b) = (&self.nodes[a].line, &self.nodes[b].line);
+
+        // The new text of the touched lines is: prefix of `a`, `text`, suffix of `b`
+        let prefix = start - first_start;
+        let len = prefix + text.len() + (last_start + b.len - end);
+        let mut chars = Chars::new(self.unit);
+        if let Some(a_chars) = &a.chars {
+            chars.append(&a_chars.slice(0, prefix), 0);
+        }
+        if self.unit != ColumnUnit::Byte {
+            let mut inserted = Chars::new(self.unit);
+            inserted.feed(0, text);
+            inserted.finish();
+            chars.append(&inserted, prefix);
+        }
+        if let Some(b_chars) = &b.chars {
+            chars.append(&b_chars.slice(end - last_start, b.len), prefix + text.len());
+        }
+
+        let mut breaks = vec![];
+        let mut scanner = Scanner::new(self.ending);
+        scanner.scan(prefix, text, |next, len| breaks.push((next, len)));
+        // A trailing lone `\r` is a line break, the next byte is no `\n`
+        scanner.finish(prefix + text.len(), |next, len| breaks.push((next, len)));
+        breaks.push((len, b.break_len));
+        let mut line_start = 0;
+        let lines: Vec<_> = breaks
+            .into_iter()
+            .map(|(next, break_len)| {
+                let line = Line::new(next - line_start, break_len, chars.slice(line_start, next));
+                line_start = next;
+                line
+            })
+            .collect();
+
+        let (left, rest) = self.split(self.root, first);
+        let (removed, right) = self.split(rest, last - first + 1);
+        self.release(removed);
+        let middle = self.build(lines);
+        let left = self.merge(left, middle);
+        self.root = self.merge(left, right);
+        Ok(())
+    }
+
+    fn checked_width(&self, line: usize) -> Result<usize> {
+        let local = self.base.local_line(line)?;
+        self.line_width(line)
+            .ok_or_else(|| self.base.line_out_of_range(local, self.line_count()))
+    }
+
+    /// Location of an offset not past the end
+    fn location(&self, offset: usize) -> line_column::ZeroBased {
+        let (line, start, node) = self.find(offset);
+        self.base.global_location(line, self.nodes[node].line.column(offset - start))
+    }
+
+    /// Line, line start and node of the last line starting at or before `offset`
+    fn find(&self, offset: usize) -> (usize, usize, usize) {
+        let (mut line, mut start) = (0, 0);
+        let mut found = None;
+        let mut tree = self.root;
+        while let Some(i) = tree {
+            let node = &self.nodes[i];
+            let before = start + self.bytes_of(node.left);
+            if offset < before {
+                tree = node.left;
+                continue;
+            }
+            line += self.lines_of(node.left);
+            found = Some((line, before, i));
+            if offset < before + node.line.len {
+                break;
+            }
+            line += 1;
+            start = before + node.line.len;
+            tree = node.right;
+        }
+        found.expect("a line index has at least one line")
+    }
+
+    /// Start and node of a line
+    fn nth(&self, mut line: usize) -> Option<(usize, usize)> {
+        let mut start = 0;
+        let mut tree = self.root;
+        while let Some(i) = tree {
+            let node = &self.nodes[i];
+            let lines = self.lines_of(node.left);
+            if line < lines {
+                tree = node.left;
+                continue;
+            }
+            start += self.bytes_of(node.left);
+            if line == lines {
+                return Some((start, i));
+            }
+            line -= lines + 1;
+            start += node.line.len;
+            tree = node.right;
+        }
+        None
+    }
+
+    #[inline]
+    fn lines_of(&self, tree: Option<usize>) -> usize {
+        tree.map_or(0, |i| self.nodes[i].lines)
+    }
+
+    #[inline]
+    fn bytes_of(&self, tree: Option<usize>) -> usize {
+        tree.map_or(0, |i| self.nodes[i].bytes)
+    }
+
+    fn update(&mut self, i: usize) {
+        let node = &self.nodes[i];
+        let lines = 1 + self.lines_of(node.left) + self.lines_of(node.right);
+        let bytes = node.line.len + self.bytes_of(node.left) + self.bytes_of(node.right);
+        let node = &mut self.nodes[i];
+        node.lines = lines;
+        node.bytes = bytes;
+    }
+
+    /// Tree of `lines` in order
+    fn build(&mut self, lines: impl IntoIterator<Item = Line>) -> Option<usize> {
+        lines.into_iter().fold(None, |tree, line| {
+            let node = self.alloc(line);
+            self.merge(tree, Some(node))
+        })
+    }
+
+    fn alloc(&mut self, line: Line) -> usize {
+        // xorshift64
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed = x;
+        let node = Node {
+            bytes: line.len,
+            line,
+            priority: x,
+            left: None,
+            right: None,
+            lines: 1,
+        };
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, tree: Option<usize>) {
+        let mut stack: Vec<_> = tree.into_iter().collect();
+        while let Some(i) = stack.pop() {
+            let node = &mut self.nodes[i];
+            stack.extend(node.left.into_iter().chain(node.right));
+            node.line.chars = None;
+            self.free.push(i);
+        }
+    }
+
+    /// Concatenate two trees, all lines of `a` come first
+    fn merge(&mut self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        let (Some(x), Some(y)) = (a, b) else {
+            return a.or(b);
+        };
+        if self.nodes[x].priority > self.nodes[y].priority {
+            let right = self.nodes[x].right;
+            self.nodes[x].right = self.merge(right, b);
+            self.update(x);
+            Some(x)
+        } else {
+            let left = self.nodes[y].left;
+            self.nodes[y].left = self.merge(a, left);
+            self.update(y);
+            Some(y)
+        }
+    }
+
+    /// Split a tree into its first `n` lines and the rest
+    fn split(&mut self, tree: Option<usize>, n: usize) -> (Option<usize>, Option<usize>) {
+        let Some(x) = tree else {
+            return (None, None);
+        };
+        let left = self.nodes[x].left;
+        let lines = self.lines_of(left);
+        if n <= lines {
+            let (a, b) = self.split(left, n);
+            self.nodes[x].left = b;
+            self.update(x);
+            (a, Some(x))
+        } else {
+            let right = self.nodes[x].right;
+            let (a, b) = self.split(right, n - lines - 1);
+            self.nodes[x].right = a;
+            self.update(x);
+            (Some(x), b)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::column::UNITS;
+    use crate::index::test::{EDITED, EDITS};
+    use crate::Stream;
+
+    fn assert_same(rope: &RopeIndex, index: &LineIndex) {
+        assert_eq!((rope.len(), rope.line_count()), (index.len(), index.line_count()));
+        for offset in 0..=index.len() {
+            let span = Span::new(Offset::new(offset), Offset::new(offset));
+            assert_eq!(rope.span_location(span).unwrap(), index.span_location(span).unwrap());
+        }
+        for line in 0..=index.line_count() {
+            assert_eq!(rope.line_offset(line), index.line_offset(line));
+            assert_eq!(rope.line_width(line), index.line_width(line));
+            for column in 0..4 {
+                let at = || line_column::ZeroBased::new(line, column);
+                assert_eq!(rope.offset_of(at()).ok(), index.offset_of(at()).ok());
+                assert_eq!(rope.offset_of_clamped(at()).ok(), index.offset_of_clamped(at()).ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_rope_edit() {
+        for &unit in UNITS {
+            for ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Any] {
+                let stream = Stream::new(EDITED.as_bytes(), 3)
+                    .with_column_unit(unit)
+                    .with_line_ending(ending);
+                let mut index = stream.into_index().unwrap();
+                let mut rope = RopeIndex::from(&index);
+                assert_same(&rope, &index);
+                for (start, end, inserted) in EDITS {
+                    let span = Span::new(Offset::new(start), Offset::new(end));
+                    rope.edit(span, inserted.as_bytes()).unwrap();
+                    index.edit(span, inserted.as_bytes()).unwrap();
+                    assert_same(&rope, &index);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rope_large() {
+        let text: String = (0..10_000).map(|i| format!("line {}\n", i)).collect();
+        let index = Stream::from(text.as_bytes()).into_index().unwrap();
+        let mut rope = RopeIndex::from(&index);
+        assert_eq!(rope.line_index(Offset::new(text.len() - 1)).unwrap().raw(), (9_999, 9));
+
+        // Join lines 100 and 101, then split line 5000
+        let start = rope.offset_of((100, 8).into()).unwrap();
+        rope.edit(Span::new(start, Offset::new(start.raw() + 1)), b" ").unwrap();
+        let offset = rope.offset_of((5_000, 4).into()).unwrap();
+        rope.edit(Span::new(offset, offset), b"\n\n").unwrap();
+        assert_eq!(rope.line_count(), index.line_count() + 1);
+        assert_eq!(rope.line_of(offset).unwrap(), 5_000);
+        assert_eq!(rope.line_index(Offset::new(offset.raw() + 2)).unwrap().raw(), (5_002, 0));
+        assert_eq!(rope.line_width(5_002), Some(5));
+        assert!(matches!(rope.line_of(Offset::new(rope.len())), Err(Error::OffsetPastEof { .. })));
+        let reversed = Span {
+            start: offset,
+            end: start,
+        };
+        assert!(matches!(rope.edit(reversed, b""), Err(Error::InvalidSpan { .. })));
+        assert!(matches!(rope.span_location(reversed), Err(Error::InvalidSpan { .. })));
+    }
+
+    #[test]
+    fn test_rope_windowed() {
+        let text: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let index = Stream::from(text.as_bytes()).with_window_lines(3).into_index().unwrap();
+        let rope = RopeIndex::from(&index);
+        let offset = Offset::new(text.len() - 3);
+        assert_eq!(rope.line_of(offset).unwrap(), 19);
+        assert_eq!(rope.line_of(offset).unwrap(), index.line_of(offset).unwrap());
+        assert_eq!(rope.line_index(offset).unwrap(), index.line_index(offset).unwrap());
+        assert!(matches!(rope.line_of(Offset::new(0)), Err(Error::OffsetEvicted { .. })));
+    }
+}
@@ -0,0 +1,235 @@
+//! Many sources sharing one offset space.
+//!
+//! Every file added to a [`SourceMap`] is assigned a disjoint range of global offsets,
+//! so a single [`Offset`] identifies both the file and the position in it.
+//! The range of a file covers its end, one offset is left between adjacent files.
+use crate::location::{line_column, Offset, Span};
+use crate::stream::{Error, Result, Stream};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Index of a file in a [`SourceMap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(usize);
+
+impl FileId {
+    pub fn raw(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+struct SourceFile<R> {
+    name: String,
+    start: usize,
+    len: usize,
+    stream: Stream<R>,
+}
+
+/// Streams of many files addressed by global offsets
+#[derive(Debug)]
+pub struct SourceMap<R> {
+    files: Vec<SourceFile<R>>,
+    /// First offset not assigned to any file
+    end: usize,
+}
+
+impl<R> Default for SourceMap<R> {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            end: 0,
+        }
+    }
+}
+
+impl<R> SourceMap<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of files
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    #[inline]
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.0].name
+    }
+
+    /// Global offsets of a file, from its first byte to its end
+    #[inline]
+    pub fn file_span(&self, file: FileId) -> Span {
+        let file = &self.files[file.0];
+        Span::new(Offset::new(file.start), Offset::new(file.start + file.len))
+    }
+
+    /// Stream of a file, which takes and returns global offsets.
+    /// Lookups go through the map, which keeps the base of every stream at the start of its file.
+    #[inline]
+    pub fn stream(&self, file: FileId) -> &Stream<R> {
+        &self.files[file.0].stream
+    }
+
+    #[inline]
+    fn stream_mut(&mut self, file: FileId) -> &mut Stream<R> {
+        &mut self.files[file.0].stream
+    }
+
+    /// File whose range contains `offset`, the end of a file belongs to it
+    pub fn file_of(&self, offset: Offset) -> Result<FileId> {
+        let offset = offset.raw();
+        if offset >= self.end {
+            return Err(Error::OffsetPastEof {
+                offset,
+                len: self.end,
+            });
+        }
+        Ok(FileId(self.files.partition_point(|file| file.start <= offset) - 1))
+    }
+}
+
+impl<R: io::Read> SourceMap<R> {
+    /// Add a file of `len` bytes, nothing is read until it is looked up.
+    /// The stream keeps its base location, its base offset is moved to the start of the file.
+    /// Lookups in the file fail with [`Error::InputTooLong`] once its stream reads past `len`.
+    pub fn add(&mut self, name: impl Into<String>, mut stream: Stream<R>, len: usize) -> FileId {
+        let start = self.end;
+        stream.set_base(start);
+        self.files.push(SourceFile {
+            name: name.into(),
+            start,
+            len,
+            stream,
+        });
+        self.end = start + len + 1;
+        FileId(self.files.len() - 1)
+    }
+
+    /// Get file, line and column number from a global offset
+    pub fn line_index(&mut self, offset: Offset) -> Result<(FileId, line_column::ZeroBased)> {
+        let file = self.file_of(offset)?;
+        let location = self.stream_mut(file).line_index(offset);
+        self.check_len(file)?;
+        Ok((file, location?))
+    }
+
+    /// Get global offset from line and column number in a file
+    pub fn offset_of(&mut self, file: FileId, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let offset = self.stream_mut(file).offset_of(line_index);
+        self.check_len(file)?;
+        let offset = offset?;
+        let end = self.file_span(file).end;
+        if offset > end {
+            return Err(Error::OffsetPastEof {
+                offset: offset.raw(),
+                len: end.raw(),
+            });
+        }
+        Ok(offset)
+    }
+
+    /// Get file and line-column locations of a span, which must not cross files
+    pub fn span_location(
+        &mut self,
+        span: Span,
+    ) -> Result<(FileId, line_column::ZeroBased, line_column::ZeroBased)> {
+        let file = self.file_of(span.start)?;
+        let end = self.file_span(file).end;
+        if span.end > end {
+            return Err(Error::OffsetPastEof {
+                offset: span.end.raw(),
+                len: end.raw(),
+            });
+        }
+        let location = self.stream_mut(file).span_location(span);
+        self.check_len(file)?;
+        let (start, end) = location?;
+        Ok((file, start, end))
+    }
+
+    /// Fail if the stream of a file read past the length it was added with,
+    /// its offsets would overlap the next file
+    fn check_len(&self, file: FileId) -> Result<()> {
+        let file = &self.files[file.0];
+        let read = file.stream.read_len();
+        if read > file.len {
+            return Err(Error::InputTooLong {
+                len: file.len,
+                read,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SourceMap<File> {
+    /// Open and add a file, its length is taken from the file system
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) -> Result<FileId> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        Ok(self.add(path.to_string_lossy(), Stream::from(file), len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut map = SourceMap::new();
+        let main_rs = "fn main() {\n    lib::f();\n}\n";
+        let main = map.add("main.rs", Stream::from(main_rs.as_bytes()), main_rs.len());
+        let lib = map.add("lib.rs", Stream::from("pub fn f() {}\n".as_bytes()), 14);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.file_span(lib), Span::new(Offset::new(29), Offset::new(43)));
+
+        let (file, location) = map.line_index(Offset::new(16)).unwrap();
+        assert_eq!((map.name(file), location.raw()), ("main.rs", (1, 4)));
+        let (file, location) = map.line_index(Offset::new(36)).unwrap();
+        assert_eq!((map.name(file), location.raw()), ("lib.rs", (0, 7)));
+        assert_eq!(map.offset_of(lib, (0, 7).into()).unwrap(), Offset::new(36));
+        assert_eq!(map.stream(lib).base(), 29);
+
+        // The end of a file belongs to it
+        let span = Span::new(Offset::new(27), Offset::new(28));
+        let (file, start, end) = map.span_location(span).unwrap();
+        assert_eq!((file, start.raw(), end.raw()), (main, (2, 1), (3, 0)));
+        assert_eq!(map.file_of(Offset::new(29)).unwrap(), lib);
+        assert!(map.span_location(Span::new(Offset::new(20), Offset::new(30))).is_err());
+        assert!(matches!(map.file_of(Offset::new(44)), Err(Error::OffsetPastEof { len: 44, .. })));
+
+        // Files can be added after lookups
+        let late = map.add("late.rs", Stream::from("x".as_bytes()), 1);
+        assert_eq!(map.line_index(Offset::new(44)).unwrap(), (late, (0, 0).into()));
+
+        // A file longer than its length would run into the next file
+        let short = map.add("short.rs", Stream::from("ab\ncd\n".as_bytes()), 3);
+        let span = map.file_span(short);
+        assert_eq!(span, Span::new(Offset::new(46), Offset::new(49)));
+        let err = map.line_index(Offset::new(47));
+        assert!(matches!(err, Err(Error::InputTooLong { len: 3, read: 6 })));
+        let err = map.offset_of(short, (1, 1).into());
+        assert!(matches!(err, Err(Error::InputTooLong { len: 3, read: 6 })));
+        assert!(map.span_location(span).is_err());
+    }
+
+    #[test]
+    fn test_add_path() {
+        let mut map = SourceMap::new();
+        let file = map.add_path(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        assert!(map.name(file).ends_with("Cargo.toml"));
+        let (found, location) = map.line_index(Offset::new(0)).unwrap();
+        assert_eq!((found, location.raw()), (file, (0, 0)));
+    }
+}
@@ -0,0 +1,394 @@
+//! Line index of bounded size for endless inputs.
+//!
+//! Only the start of every Kth line (a checkpoint) is kept. Other lines are found by seeking
+//! the reader back to the nearest checkpoint and scanning again.
+use crate::column::{Chars, ColumnUnit};
+use crate::line_ending::{LineEnding, Scanner};
+use crate::location::{line_column, Offset};
+use crate::stream::{Error, Result};
+use std::io;
+use std::mem::size_of;
+
+/// Line starts of every `interval`th line, `starts[i]` is the start of line `i * interval`
+#[derive(Debug)]
+struct Checkpoints {
+    starts: Vec<usize>,
+    interval: usize,
+    /// Lines found so far
+    lines: usize,
+    /// Most starts kept
+    capacity: usize,
+}
+
+impl Checkpoints {
+    /// A line starts at `start`
+    fn record(&mut self, start: usize) {
+        if self.lines.is_multiple_of(self.interval) && self.starts.len() == self.capacity {
+            // Keep every other checkpoint, lines between them are twice as far
+            self.interval *= 2;
+            let kept = self.starts.len().div_ceil(2);
+            for i in 0..kept {
+                self.starts[i] = self.starts[2 * i];
+            }
+            self.starts.truncate(kept);
+        }
+        if self.lines.is_multiple_of(self.interval) {
+            if self.starts.len() == self.starts.capacity() {
+                // Grow like a `Vec`, but never allocate past the limit
+                let grown = (self.starts.len() * 2).max(4).min(self.capacity);
+                self.starts.reserve_exact(grown - self.starts.len());
+            }
+            self.starts.push(start);
+        }
+        self.lines += 1;
+    }
+}
+
+/// A line found by scanning again
+struct Line {
+    start: usize,
+    /// Bytes of the line, including its line break
+    bytes: Vec<u8>,
+    break_len: usize,
+}
+
+/// A stream keeping only every Kth line start, so its memory does not grow with the input.
+///
+/// Lookups seek the reader back to the nearest checkpoint, costing up to K lines of reading.
+/// When the checkpoints would exceed the memory limit, every other one is dropped and K doubles.
+#[derive(Debug)]
+pub struct SparseStream<Reader> {
+    reader: Reader,
+    buffer: Vec<u8>,
+    scanner: Scanner,
+    unit: ColumnUnit,
+    checkpoints: Checkpoints,
+    /// Read length
+    len: usize,
+    eof: bool,
+}
+
+impl<R> SparseStream<R> {
+    const BUF_SIZE: usize = 1024;
+    const MEMORY_LIMIT: usize = 1024 * 1024;
+
+    /// Number of lines read so far
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.checkpoints.lines
+    }
+
+    /// Read length
+    #[inline]
+    pub fn read_len(&self) -> usize {
+        self.len
+    }
+
+    /// Current distance between checkpoints in lines
+    #[inline]
+    pub fn checkpoint_interval(&self) -> usize {
+        self.checkpoints.interval
+    }
+
+    /// Bytes allocated for checkpoints, never more than the memory limit
+    #[inline]
+    pub fn memory_used(&self) -> usize {
+        self.checkpoints.starts.capacity() * size_of::<usize>()
+    }
+
+    /// Bound the memory of checkpoints, at least one checkpoint is kept.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        assert_eq!(self.len, 0, "memory limit must be set before reading");
+        self.checkpoints.capacity = (bytes / size_of::<usize>()).max(1);
+        self.checkpoints.starts.shrink_to(self.checkpoints.capacity);
+        self
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.unit
+    }
+
+    /// Set the unit in which columns are counted.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_column_unit(mut self, unit: ColumnUnit) -> Self {
+        assert_eq!(self.len, 0, "column unit must be set before reading");
+        self.unit = unit;
+        self
+    }
+
+    /// Which byte sequences terminate a line
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.scanner.ending()
+    }
+
+    /// Set which byte sequences terminate a line.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_line_ending(mut self, ending: LineEnding) -> Self {
+        assert_eq!(self.len, 0, "line ending must be set before reading");
+        self.scanner = Scanner::new(ending);
+        self
+    }
+}
+
+impl<R: io::Read + io::Seek> SparseStream<R> {
+    /// Keep the start of every `interval`th line, which must be positive
+    pub fn new(reader: R, interval: usize) -> Self {
+        assert!(interval > 0, "checkpoint interval must be positive");
+        let mut checkpoints = Checkpoints {
+            starts: Vec::new(),
+            interval,
+            lines: 0,
+            capacity: Self::MEMORY_LIMIT / size_of::<usize>(),
+        };
+        checkpoints.record(0);
+        Self {
+            reader,
+            buffer: vec![0; Self::BUF_SIZE],
+            scanner: Scanner::default(),
+            unit: ColumnUnit::default(),
+            checkpoints,
+            len: 0,
+            eof: false,
+        }
+    }
+
+    /// Get line of offset
+    pub fn line_of(&mut self, offset: Offset) -> Result<usize> {
+        Ok(self.line_index(offset)?.line)
+    }
+
+    /// Get line and column number from offset, the column is counted in [`Self::column_unit`]
+    pub fn line_index(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        let offset = offset.raw();
+        while self.len <= offset && self.forward()? > 0 {}
+        if offset >= self.len {
+            return Err(Error::OffsetPastEof {
+                offset,
+                len: self.len,
+            });
+        }
+        let starts = &self.checkpoints.starts;
+        let checkpoint = starts.partition_point(|&start| start <= offset) - 1;
+        let (line, found) = self.rescan(checkpoint, |_, end| end > offset)?;
+        Ok((line, self.chars(&found).column(found.start, found.end(), offset)).into())
+    }
+
+    /// Get offset from line and column number, the column is counted in [`Self::column_unit`]
+    pub fn offset_of(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (_, found) = self.line(line_index.line)?;
+        let chars = self.chars(&found);
+        Ok(Offset::new(chars.offset(found.start, found.end(), line_index.column)))
+    }
+
+    /// Get offset from line and column number,
+    /// fails with [`Error::ColumnOutOfRange`] if the column exceeds the line length (excluding the line break)
+    pub fn offset_of_strict(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let (_, found) = self.line(line)?;
+        let chars = self.chars(&found);
+        let width = chars.column(found.start, found.content_end(), found.content_end());
+        if column > width {
+            return Err(Error::ColumnOutOfRange {
+                line,
+                column,
+                width,
+            });
+        }
+        Ok(Offset::new(chars.offset(found.start, found.end(), column)))
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub fn offset_of_clamped(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = line_index.raw();
+        let (_, found) = self.line(line)?;
+        let chars = self.chars(&found);
+        let width = chars.column(found.start, found.content_end(), found.content_end());
+        Ok(Offset::new(chars.offset(found.start, found.end(), column.min(width))))
+    }
+
+    /// Drain the reader
+    pub fn drain(&mut self) -> Result<()> {
+        while self.forward()? > 0 {}
+        Ok(())
+    }
+
+    /// Find a line, reading until its end is known
+    fn line(&mut self, line: usize) -> Result<(usize, Line)> {
+        while self.checkpoints.lines <= line + 1 && self.forward()? > 0 {}
+        if line >= self.checkpoints.lines {
+            return Err(Error::LineOutOfRange {
+                line,
+                lines: self.checkpoints.lines,
+            });
+        }
+        let checkpoint = line / self.checkpoints.interval;
+        self.rescan(checkpoint, |found, _| found == line)
+    }
+
+    /// Characters of a line found by scanning again
+    fn chars(&self, line: &Line) -> Chars {
+        let mut chars = Chars::new(self.unit);
+        chars.feed(line.start, &line.bytes);
+        chars.finish();
+        chars
+    }
+
+    /// Scan again from a checkpoint until `done(line, line_end)` holds for a complete line,
+    /// or the read bytes end. The reader position is restored.
+    fn rescan(
+        &mut self,
+        checkpoint: usize,
+        mut done: impl FnMut(usize, usize) -> bool,
+    ) -> Result<(usize, Line)> {
+        let pos = self.reader.stream_position()?;
+        // The reader may not start at 0
+        let origin = pos - self.len as u64;
+        let mut line = checkpoint * self.checkpoints.interval;
+        let mut start = self.checkpoints.starts[checkpoint];
+        self.reader.seek(io::SeekFrom::Start(origin + start as u64))?;
+
+        let mut scanner = Scanner::new(self.line_ending());
+        let mut bytes = Vec::new();
+        let mut breaks = Vec::new();
+        let mut read = start;
+        let found = loop {
+            let want = self.buffer.len().min(self.len - read);
+            let n = match want {
+                0 => 0,
+                _ => self.reader.read(&mut self.buffer[..want])?,
+            };
+            if n == 0 {
+                if self.eof {
+                    scanner.finish(read, |next, len| breaks.push((next, len)));
+                }
+            } else {
+                scanner.scan(read, &self.buffer[..n], |next, len| breaks.push((next, len)));
+                bytes.extend_from_slice(&self.buffer[..n]);
+                read += n;
+            }
+
+            let mut found = None;
+            for (next, len) in breaks.drain(..) {
+                if done(line, next) {
+                    bytes.truncate(next - start);
+                    found = Some((start, len as usize));
+                    break;
+                }
+                bytes.drain(..next - start);
+                line += 1;
+                start = next;
+            }
+            if let Some((start, break_len)) = found {
+                break Line {
+                    start,
+                    bytes,
+                    break_len,
+                };
+            }
+            if n == 0 {
+                // The last line, without a line break
+                break Line {
+                    start,
+                    bytes,
+                    break_len: 0,
+                };
+            }
+        };
+        self.reader.seek(io::SeekFrom::Start(pos))?;
+        Ok((line, found))
+    }
+
+    /// Try to get more bytes and update states
+    fn forward(&mut self) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        let n = self.reader.read(&mut self.buffer)?;
+        let checkpoints = &mut self.checkpoints;
+        if n == 0 {
+            self.eof = true;
+            self.scanner.finish(self.len, |next, _| checkpoints.record(next));
+            return Ok(0);
+        }
+        let bytes = &self.buffer[..n];
+        self.scanner.scan(self.len, bytes, |next, _| checkpoints.record(next));
+        self.len += n;
+        Ok(n)
+    }
+}
+
+impl Line {
+    #[inline]
+    fn end(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+
+    #[inline]
+    fn content_end(&self) -> usize {
+        self.end() - self.break_len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Stream;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sparse_stream() {
+        let text: String = (0..500).map(|i| format!("{}\u{e9}\r\n", "x".repeat(i % 7))).collect();
+        let stream = Stream::from(text.as_bytes())
+            .with_column_unit(ColumnUnit::Char)
+            .with_line_ending(LineEnding::CrLf);
+        let index = stream.into_index().unwrap();
+
+        let mut sparse = Stream::from(Cursor::new(text.as_bytes()))
+            .with_column_unit(ColumnUnit::Char)
+            .with_line_ending(LineEnding::CrLf)
+            .into_sparse(4)
+            .with_memory_limit(16 * size_of::<usize>());
+        for offset in (0..text.len()).step_by(7) {
+            let offset = Offset::new(offset);
+            assert_eq!(sparse.line_index(offset).unwrap(), index.line_index(offset).unwrap());
+        }
+        for line in (0..=500).rev().step_by(3) {
+            for column in [0, 2, 9] {
+                let at = || line_column::ZeroBased::new(line, column);
+                assert_eq!(sparse.offset_of(at()).unwrap(), index.offset_of(at()).unwrap());
+                let clamped = sparse.offset_of_clamped(at()).unwrap();
+                assert_eq!(clamped, index.offset_of_clamped(at()).unwrap());
+                let strict = sparse.offset_of_strict(at()).ok();
+                assert_eq!(strict, index.offset_of_strict(at()).ok());
+            }
+        }
+
+        assert_eq!(sparse.line_count(), 501);
+        assert!(sparse.memory_used() <= 16 * size_of::<usize>());
+        assert_eq!(sparse.checkpoint_interval(), 32);
+
+        // A limit which is no power of two is not overshot by growing the checkpoints
+        let mut small = SparseStream::new(Cursor::new(text.as_bytes()), 1)
+            .with_line_ending(LineEnding::CrLf)
+            .with_memory_limit(3 * size_of::<usize>());
+        small.drain().unwrap();
+        assert_eq!(small.memory_used(), 3 * size_of::<usize>());
+        let last = Offset::new(text.len() - 1);
+        assert_eq!(small.line_of(last).unwrap(), index.line_of(last).unwrap());
+        let eof = sparse.line_of(Offset::new(text.len()));
+        assert!(matches!(eof, Err(Error::OffsetPastEof { .. })));
+        assert!(index.line_of(Offset::new(text.len())).is_err());
+        assert!(matches!(sparse.offset_of((501, 0).into()), Err(Error::LineOutOfRange { .. })));
+    }
+}
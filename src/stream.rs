@@ -1,30 +1,241 @@
 #![allow(dead_code)]
-use crate::location::{line_column, Offset};
-use std::io;
+use crate::column::ColumnUnit;
+use crate::index::{Base, Fill, LineIndex, Need};
+use crate::line_ending::LineEnding;
+use crate::location::{line_column, Offset, Span};
+use crate::retain::Retained;
+use crate::sparse::SparseStream;
+use crate::visual::TabStops;
+use std::borrow::Cow;
+use std::{error, fmt, io};
 
 /// A stream which can be used to convert between offsets and line-column numbers.
+///
+/// The stream is a reader itself: bytes read through [`io::Read`] or [`io::BufRead`] are indexed
+/// as they pass, so the location of any consumed offset can be looked up.
+/// Once reading has started, bytes read ahead by lookups are kept until they are consumed.
+/// Reading fails with [`Error::ReadAfterLookup`] if lookups read bytes before the first read,
+/// so start reading before looking anything up.
+///
+/// Offsets and locations are relative to the enclosing document when a base is set,
+/// see [`Self::set_base_location`].
 #[derive(Debug)]
 pub struct Stream<Reader> {
     reader: Reader,
-    base: usize, // For future use
-
-    lines: Vec<usize>,
-    next_offset: usize,
-    current_line: usize,
+    index: LineIndex,
     buffer: Vec<u8>,
+    retained: Option<Retained>,
+    eof: bool,
+    /// Whether the stream is read through `io::Read`
+    reading: bool,
+    /// Indexed bytes not consumed through `io::Read` yet, from `pending_pos`
+    pending: Vec<u8>,
+    pending_pos: usize,
+    cursor: Cursor,
+    window: Option<Window>,
+    tabs: TabStops,
+}
+
+/// How much of the line table a windowed stream keeps
+#[derive(Debug, Clone, Copy)]
+enum Window {
+    /// Lines overlapping the last N bytes
+    Bytes(usize),
+    /// The last N lines
+    Lines(usize),
+}
+
+/// Position moved by [`Stream::advance`]
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// End of the last unit counted in `column`, `offset` may lie inside the next unit
+    counted: usize,
+    /// Offset in the document once the line of the cursor is evicted
+    evicted: Option<usize>,
 }
 
 impl<R> Stream<R> {
     const BUF_SIZE: usize = 1024;
 
+    /// Offset of the first byte in the enclosing document
     #[inline]
     pub fn base(&self) -> usize {
-        self.base
+        self.index.base.offset
+    }
+
+    /// Location of the first byte in the enclosing document
+    #[inline]
+    pub fn base_location(&self) -> line_column::ZeroBased {
+        self.index.base_location()
     }
 
     #[inline]
     pub fn line_offset(&self, line: usize) -> Option<Offset> {
-        self.lines.get(line).copied().map(Offset::new)
+        self.index.line_offset(line)
+    }
+
+    /// Number of lines read so far, including evicted lines
+    #[inline]
+    pub fn line_count(&self) -> usize {
+        self.index.base.evicted_lines + self.index.line_count()
+    }
+
+    /// Unit in which columns are counted
+    #[inline]
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.index.column_unit()
+    }
+
+    /// Set the unit in which columns are counted.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_column_unit(mut self, unit: ColumnUnit) -> Self {
+        self.index.set_column_unit(unit);
+        self
+    }
+
+    /// Which byte sequences terminate a line
+    #[inline]
+    pub fn line_ending(&self) -> LineEnding {
+        self.index.line_ending()
+    }
+
+    /// Set which byte sequences terminate a line.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_line_ending(mut self, ending: LineEnding) -> Self {
+        self.index.set_line_ending(ending);
+        self
+    }
+
+    /// Keep read bytes in memory, up to `limit` bytes, so that [`Self::line_text`] and [`Self::slice`] work.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn with_retained_source(mut self, limit: usize) -> Self {
+        assert_eq!(self.index.len, 0, "retained source must be set before reading");
+        assert!(self.window.is_none(), "retained source cannot be used with a window");
+        self.retained = Some(Retained::new(limit));
+        self
+    }
+
+    /// Store line starts as [`crate::CompactLines`], in about half the memory, lines read so far
+    /// are converted. Lookups stay O(log n), but evicting lines from a window re-encodes them.
+    pub fn with_compact_lines(mut self) -> Self {
+        self.index.lines.compact();
+        self
+    }
+
+    /// Keep only lines overlapping the last `bytes` read bytes, older lines are evicted.
+    /// Offsets and lines keep counting from the start, looking up an evicted one fails
+    /// with [`Error::OffsetEvicted`] or [`Error::LineEvicted`].
+    ///
+    /// Lines are evicted while reading, except the lines a lookup still needs, and freed
+    /// in batches once they outnumber the kept ones. [`Self::advance`] keeps the lines from
+    /// the cursor on, but once another lookup evicts the line of the cursor, [`Self::advance`]
+    /// and [`Self::position`] fail with [`Error::OffsetEvicted`].
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read, or if the source is retained.
+    pub fn with_window_bytes(self, bytes: usize) -> Self {
+        self.with_window(Window::Bytes(bytes))
+    }
+
+    /// Keep only the last `lines` lines, older lines are evicted, see [`Self::with_window_bytes`].
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read, if the source is retained, or if `lines` is 0.
+    pub fn with_window_lines(self, lines: usize) -> Self {
+        assert!(lines > 0, "window must keep a line");
+        self.with_window(Window::Lines(lines))
+    }
+
+    fn with_window(mut self, window: Window) -> Self {
+        assert_eq!(self.index.len, 0, "window must be set before reading");
+        assert!(self.retained.is_none(), "retained source cannot be used with a window");
+        self.window = Some(window);
+        self
+    }
+
+    /// Evict lines which left the window
+    #[inline]
+    fn slide(&mut self) {
+        self.slide_before(usize::MAX);
+    }
+
+    /// Evict lines which left the window, up to the line `keep`.
+    /// Returns the number of bytes and lines freed, by which local offsets and lines shift.
+    fn slide_before(&mut self, keep: usize) -> (usize, usize) {
+        let lines = &self.index.lines;
+        let first = match self.window {
+            None => return (0, 0),
+            Some(Window::Bytes(bytes)) => {
+                let start = self.index.len.saturating_sub(bytes);
+                lines.search(start)
+            }
+            Some(Window::Lines(n)) => lines.len().saturating_sub(n),
+        };
+        let first = first.min(keep);
+        if first <= self.index.base.stale_lines {
+            return (0, 0);
+        }
+        let cursor = &mut self.cursor;
+        if cursor.evicted.is_none() && cursor.line < first {
+            cursor.evicted = Some(self.index.global_offset(cursor.offset).raw());
+        }
+        let (bytes, lines) = self.index.evict(first);
+        if cursor.evicted.is_none() {
+            cursor.offset -= bytes;
+            cursor.line -= lines;
+            cursor.counted -= bytes;
+        }
+        (bytes, lines)
+    }
+
+    /// Fails if the line of the cursor is evicted
+    fn check_cursor(&self) -> Result<()> {
+        match self.cursor.evicted {
+            Some(offset) => Err(Error::OffsetEvicted {
+                offset,
+                start: self.index.global_offset(0).raw(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Where tabs stop in visual columns
+    #[inline]
+    pub fn tab_stops(&self) -> &TabStops {
+        &self.tabs
+    }
+
+    /// Set where tabs stop in visual columns, every 8 columns by default.
+    /// See [`Self::visual_location`] and [`Self::offset_of_visual`].
+    pub fn with_tab_stops(mut self, tabs: TabStops) -> Self {
+        self.tabs = tabs;
+        self
+    }
+
+    /// Number of bytes kept in memory
+    #[inline]
+    pub fn retained_len(&self) -> usize {
+        self.retained.as_ref().map_or(0, Retained::len)
+    }
+
+    /// Get retained bytes of `start..end`
+    fn retained(&self, start: usize, end: usize) -> Result<Cow<'_, [u8]>> {
+        match &self.retained {
+            Some(retained) if end <= retained.len() => Ok(retained.slice(start, end)),
+            _ => Err(Error::NotRetained {
+                offset: start.max(self.retained_len()),
+                retained: self.retained_len(),
+            }),
+        }
     }
 }
 
@@ -32,11 +243,16 @@ impl<R: io::Read> Stream<R> {
     pub fn new(reader: R, buffer_size: usize) -> Self {
         Self {
             reader,
-            base: 0,
-            lines: vec![0],
-            next_offset: 0,
-            current_line: 0,
+            index: LineIndex::default(),
             buffer: vec![0; buffer_size],
+            retained: None,
+            eof: false,
+            reading: false,
+            pending: Vec::new(),
+            pending_pos: 0,
+            cursor: Cursor::default(),
+            window: None,
+            tabs: TabStops::default(),
         }
     }
 
@@ -45,91 +261,436 @@ impl<R: io::Read> Stream<R> {
         Self::new(reader, Self::BUF_SIZE)
     }
 
+    /// Set the offset of the first byte in the enclosing document, keeping the base location
     #[inline]
     pub fn set_base(&mut self, base: usize) {
-        self.base = base;
+        self.index.base.offset = base;
+    }
+
+    /// Report offsets and locations relative to an enclosing document,
+    /// in which the stream starts at `offset` and `location`.
+    /// Columns of the first line are shifted by the base column.
+    pub fn set_base_location(&mut self, offset: Offset, location: line_column::ZeroBased) {
+        let (line, column) = location.raw();
+        self.index.base = Base {
+            offset: offset.raw(),
+            line,
+            column,
+            ..self.index.base
+        };
     }
 
+    /// Report offsets and locations relative to the stream itself
     #[inline]
     pub fn reset(&mut self) {
-        self.set_base(0);
+        self.index.base = Base {
+            offset: 0,
+            line: 0,
+            column: 0,
+            ..self.index.base
+        };
     }
 
-    /// Read length
+    /// Read length, including evicted bytes
     #[inline]
     pub fn read_len(&self) -> usize {
-        self.next_offset
+        self.index.base.evicted + self.index.len
     }
 
-    /// Get offset from line and column number
-    pub fn offset_of(&mut self, line_index: line_column::ZeroBased) -> io::Result<Offset> {
-        let (line, col) = line_index.raw();
-        loop {
-            if let Some(offset) = self.lines.get(line) {
-                break Ok(Offset::new(offset + col));
-            }
+    /// Get offset from line and column number, the column is counted in [`Self::column_unit`]
+    pub fn offset_of(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.slide();
+        self.fill(Need::Column(self.index.local_line(line_index.line)?))?;
+        self.index.offset_of(line_index)
+    }
 
-            if self.forward()? == 0 {
-                break Err(io_error(format!("Invalid line index: ({}, {})", line, col)));
-            }
-        }
+    /// Get offset from line and column number,
+    /// fails with [`Error::ColumnOutOfRange`] if the column exceeds the line length (excluding the line break)
+    pub fn offset_of_strict(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.slide();
+        self.fill(Need::Line(self.index.local_line(line_index.line)?))?;
+        self.index.offset_of_strict(line_index)
+    }
+
+    /// Get offset from line and column number,
+    /// a column exceeding the line length snaps to the end of the line (before the line break)
+    pub fn offset_of_clamped(&mut self, line_index: line_column::ZeroBased) -> Result<Offset> {
+        self.slide();
+        self.fill(Need::Line(self.index.local_line(line_index.line)?))?;
+        self.index.offset_of_clamped(line_index)
     }
 
-    /// Get line and column number from offset
-    pub fn line_index(&mut self, offset: Offset) -> io::Result<line_column::ZeroBased> {
-        let line = self.line_of(offset)?;
-        let line_offset = self.lines.get(line).unwrap();
-        let col = offset.raw() - line_offset;
-        Ok((line, col).into())
+    /// Get line and column number from offset, the column is counted in [`Self::column_unit`]
+    pub fn line_index(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        self.slide();
+        let line = self.search_line(offset)?;
+        self.fill(Need::Column(self.index.local_line(line)?))?;
+        let (line, offset) = (self.index.local_line(line)?, self.index.local_offset(offset)?);
+        Ok(self.index.global_location(line, self.index.column(line, offset)))
+    }
+
+    /// Get start and end line-column locations of a span, the end is exclusive.
+    /// The end line is searched forward from the start line, a span may end at EOF.
+    pub fn span_location(
+        &mut self,
+        span: Span,
+    ) -> Result<(line_column::ZeroBased, line_column::ZeroBased)> {
+        span.check()?;
+        self.slide();
+        // Reading past the end completes both lines
+        let (start, end) =
+            (self.index.local_offset(span.start)?, self.index.local_offset(span.end)?);
+        self.fill_keeping(Need::Past(end), start)?;
+        self.index.span_location(span)
+    }
+
+    /// Get text of a line, excluding its line break, from the retained source.
+    /// Invalid UTF-8 is replaced with `U+FFFD`.
+    pub fn line_text(&mut self, line: usize) -> Result<Cow<'_, str>> {
+        let line = self.index.local_line(line)?;
+        self.fill(Need::Line(line))?;
+        let bytes = self.retained(self.index.lines.start(line), self.index.content_end(line))?;
+        Ok(match bytes {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        })
+    }
+
+    /// Get line and visual column of an offset, where a tab advances to the next tab stop.
+    /// The line is taken from the retained source.
+    pub fn visual_location(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        let (line, offset) = self.read_line_of(offset)?;
+        let start = self.index.lines.start(line);
+        let bytes = self.retained(start, self.index.content_end(line))?;
+        let column = self.tabs.column(&bytes, offset - start);
+        Ok(self.index.global_location(line, column))
+    }
+
+    /// Get offset from line and visual column number, the line is taken from the retained source.
+    /// A column inside a tab resolves to the tab, a column past the line to its end.
+    pub fn offset_of_visual(&mut self, location: line_column::ZeroBased) -> Result<Offset> {
+        let (line, column) = self.index.local_location(location)?;
+        self.fill(Need::Line(line))?;
+        let start = self.index.lines.start(line);
+        let bytes = self.retained(start, self.index.content_end(line))?;
+        Ok(self.index.global_offset(start + self.tabs.offset(&bytes, column)))
+    }
+
+    /// Local line and offset of an offset, reading until the line is complete
+    fn read_line_of(&mut self, offset: Offset) -> Result<(usize, usize)> {
+        let line = self.search_line(offset)?;
+        self.fill(Need::Line(self.index.local_line(line)?))?;
+        Ok((self.index.local_line(line)?, self.index.local_offset(offset)?))
+    }
+
+    /// Get bytes of a span from the retained source
+    pub fn slice(&mut self, span: Span) -> Result<Cow<'_, [u8]>> {
+        span.check()?;
+        let (start, end) =
+            (self.index.local_offset(span.start)?, self.index.local_offset(span.end)?);
+        self.fill(Need::Past(end))?;
+        self.retained(start, end)
     }
 
     /// Get line of offset
-    pub fn line_of(&mut self, offset: Offset) -> io::Result<usize> {
-        let offset = offset.raw();
+    pub fn line_of(&mut self, offset: Offset) -> Result<usize> {
+        self.slide();
+        self.search_line(offset)
+    }
 
-        let mut begin = 0;
-        loop {
-            let n = self.lines.len();
-            if let Some(i) = binary_search_between(&self.lines[begin..], offset) {
-                break Ok(i);
-            }
-            if n > 0 {
-                begin = n - 1;
-            }
+    /// Line of an offset, reading until the line is found
+    fn search_line(&mut self, offset: Offset) -> Result<usize> {
+        self.fill(Need::Past(self.index.local_offset(offset)?))?;
+        let offset = self.index.local_offset(offset)?;
+        if offset >= self.index.len {
+            return Err(self.index.past_eof(offset));
+        }
+        Ok(self.index.base.global_line(self.index.lines.search(offset)))
+    }
 
-            if self.forward()? == 0 {
-                if offset < self.next_offset {
-                    break Ok(self.current_line);
-                }
-                break Err(io_error("Invalid offset, exceed EOF"));
-            }
+    /// Width of a line in [`Self::column_unit`], excluding its line break.
+    /// Returns `None` if the line does not exist, fails if it is before the base or evicted.
+    pub(crate) fn line_width(&mut self, line: usize) -> Result<Option<usize>> {
+        self.slide();
+        match self.fill(Need::Line(self.index.local_line(line)?)) {
+            Ok(()) | Err(Error::LineOutOfRange { .. }) => Ok(self.index.line_width(line)),
+            Err(err) => Err(err),
         }
     }
 
+    /// Index until `need` is met, evicting lines which leave the window on the way,
+    /// except the lines from those of `need` and of the local offset `keep` on.
+    /// Local offsets and lines shift, so they are found again afterwards.
+    fn fill_keeping(&mut self, mut need: Need, mut keep: usize) -> Result<()> {
+        while self.index.wants(need) && self.forward()? > 0 {
+            let first = self.index.needed_line(need);
+            let (bytes, lines) = self.slide_before(first.min(self.index.lines.search(keep)));
+            need = need.shifted(bytes, lines);
+            keep = keep.saturating_sub(bytes);
+        }
+        self.index.check(need)
+    }
+
     /// Try to get more bytes and update states
     fn forward(&mut self) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
         let n = self.reader.read(&mut self.buffer)?;
+        if n == 0 {
+            self.eof = true;
+            self.index.finish();
+            return Ok(0);
+        }
 
-        for (offset, b) in self.buffer.iter().take(n).enumerate() {
-            if *b == b'\n' {
-                self.current_line += 1;
-                self.lines.push(self.next_offset + offset + 1); // next line begin
-                continue;
-            }
+        let bytes = &self.buffer[..n];
+        self.index.push(bytes);
+        if let Some(retained) = &mut self.retained {
+            retained.push(bytes);
+        }
+        if self.reading {
+            self.pending.extend_from_slice(bytes);
         }
-        self.next_offset += n;
         Ok(n)
     }
 
+    /// Move the cursor `n` bytes forward, up to EOF.
+    /// Fails with [`Error::OffsetPastEof`] and does not move if that passes EOF.
+    /// Only the lines and characters passed over are visited, no search is needed.
+    pub fn advance(&mut self, n: usize) -> Result<()> {
+        self.slide();
+        self.check_cursor()?;
+        let Some(target) = self.cursor.offset.checked_add(n) else {
+            return Err(Error::OffsetPastEof {
+                offset: usize::MAX,
+                len: self.index.global_offset(self.index.len).raw(),
+            });
+        };
+        // The line of `target` becomes complete, lines before the cursor may be freed
+        self.fill_keeping(Need::Past(target), self.cursor.offset)?;
+        let target = self.cursor.offset + n;
+
+        let cursor = &mut self.cursor;
+        let lines = &self.index.lines;
+        if lines.get(cursor.line + 1).is_some_and(|next| next <= target) {
+            cursor.line += 1;
+            while lines.get(cursor.line + 1).is_some_and(|next| next <= target) {
+                cursor.line += 1;
+            }
+            cursor.column = 0;
+            cursor.counted = lines.start(cursor.line);
+        }
+        let end = self.index.line_end(cursor.line);
+        let (units, counted) = self.index.chars.count(cursor.counted, end, target);
+        cursor.column += units;
+        cursor.counted = counted;
+        cursor.offset = target;
+        Ok(())
+    }
+
+    /// Offset and location of the cursor, see [`Self::advance`].
+    /// Fails with [`Error::OffsetEvicted`] once the line of the cursor is evicted.
+    #[inline]
+    pub fn position(&self) -> Result<(Offset, line_column::ZeroBased)> {
+        self.check_cursor()?;
+        let Cursor {
+            offset,
+            line,
+            column,
+            ..
+        } = self.cursor;
+        Ok((self.index.global_offset(offset), self.index.global_location(line, column)))
+    }
+
+    /// Offset of the next byte returned by [`io::Read`]
+    #[inline]
+    pub fn consumed(&self) -> Offset {
+        Offset::new(self.base() + self.read_len() - (self.pending.len() - self.pending_pos))
+    }
+
     /// Drain the reader, consume the reader
-    pub fn drain(&mut self) -> io::Result<()> {
+    pub fn drain(&mut self) -> Result<()> {
         loop {
+            self.slide();
             let n = self.forward()?;
             if n == 0 {
                 return Ok(());
             }
         }
     }
+
+    /// Drain the reader and freeze the line index
+    pub fn into_index(mut self) -> Result<LineIndex> {
+        self.drain()?;
+        self.index.free();
+        Ok(self.index)
+    }
+
+    /// Drain the reader and copy the line index, keeping the stream usable
+    pub fn to_index(&mut self) -> Result<LineIndex> {
+        self.drain()?;
+        let mut index = self.index.clone();
+        index.free();
+        Ok(index)
+    }
+}
+
+impl<R: io::Read + io::Seek> Stream<R> {
+    /// Keep only the start of every `interval`th line, see [`SparseStream`].
+    /// The line ending and column unit are kept.
+    ///
+    /// # Panics
+    /// Panics if any byte has already been read.
+    pub fn into_sparse(self, interval: usize) -> SparseStream<R> {
+        assert_eq!(self.index.len, 0, "sparse lines must be set before reading");
+        let (ending, unit) = (self.line_ending(), self.column_unit());
+        SparseStream::new(self.reader, interval)
+            .with_line_ending(ending)
+            .with_column_unit(unit)
+    }
+
+    /// Get bytes of a line, excluding its line break, by seeking the reader
+    pub fn fetch_line(&mut self, line: usize) -> Result<Vec<u8>> {
+        self.slide();
+        self.fill(Need::Line(self.index.local_line(line)?))?;
+        let line = self.index.local_line(line)?;
+        let start = self.index.lines.start(line);
+        let end = self.index.content_end(line);
+        self.fetch(start, end)
+    }
+
+    /// Get line and visual column of an offset, see [`Self::visual_location`].
+    /// The line is read again by seeking the reader.
+    pub fn fetch_visual_location(&mut self, offset: Offset) -> Result<line_column::ZeroBased> {
+        self.slide();
+        let (line, offset) = self.read_line_of(offset)?;
+        let start = self.index.lines.start(line);
+        let bytes = self.fetch(start, self.index.content_end(line))?;
+        let column = self.tabs.column(&bytes, offset - start);
+        Ok(self.index.global_location(line, column))
+    }
+
+    /// Get offset from line and visual column number, see [`Self::offset_of_visual`].
+    /// The line is read again by seeking the reader.
+    pub fn fetch_offset_of_visual(&mut self, location: line_column::ZeroBased) -> Result<Offset> {
+        self.slide();
+        self.fill(Need::Line(self.index.local_location(location.clone())?.0))?;
+        let (line, column) = self.index.local_location(location)?;
+        let start = self.index.lines.start(line);
+        let bytes = self.fetch(start, self.index.content_end(line))?;
+        Ok(self.index.global_offset(start + self.tabs.offset(&bytes, column)))
+    }
+
+    /// Get bytes of a span by seeking the reader
+    pub fn fetch_span(&mut self, span: Span) -> Result<Vec<u8>> {
+        span.check()?;
+        self.slide();
+        let (start, end) =
+            (self.index.local_offset(span.start)?, self.index.local_offset(span.end)?);
+        self.fill_keeping(Need::Past(end), start)?;
+        let (start, end) =
+            (self.index.local_offset(span.start)?, self.index.local_offset(span.end)?);
+        self.fetch(start, end)
+    }
+
+    /// Get text of a line, invalid UTF-8 is replaced with `U+FFFD`
+    pub fn fetch_line_text(&mut self, line: usize) -> Result<String> {
+        let bytes = self.fetch_line(line)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Get text of a span, invalid UTF-8 is replaced with `U+FFFD`
+    pub fn fetch_span_text(&mut self, span: Span) -> Result<String> {
+        let bytes = self.fetch_span(span)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Read `start..end`, which has been read before, and restore the reader position
+    fn fetch(&mut self, start: usize, end: usize) -> Result<Vec<u8>> {
+        let pos = self.reader.stream_position()?;
+        // The reader may not start at 0
+        let origin = pos - self.index.len as u64;
+
+        let mut bytes = vec![0; end - start];
+        self.reader.seek(io::SeekFrom::Start(origin + start as u64))?;
+        let read = self.reader.read_exact(&mut bytes);
+        self.reader.seek(io::SeekFrom::Start(pos))?;
+        read?;
+        Ok(bytes)
+    }
+
+    /// Drain the reader and write its line index, see [`LineIndex::save`]
+    pub fn save_index<W: io::Write>(&mut self, writer: W) -> Result<()> {
+        self.drain()?;
+        let pos = self.reader.stream_position()?;
+        // The reader may not start at 0
+        self.reader.seek(io::SeekFrom::Start(pos - self.index.len as u64))?;
+        let saved = self.index.save(writer, &mut self.reader);
+        self.reader.seek(io::SeekFrom::Start(pos))?;
+        saved
+    }
+
+    /// Create a stream from a line index saved for `reader`, see [`LineIndex::load`].
+    /// The stream keeps the line ending, column unit and base of the saved one.
+    pub fn load_index<I: io::Read>(mut reader: R, index: I) -> Result<Self> {
+        let index = LineIndex::load(index, &mut reader)?;
+        reader.seek(io::SeekFrom::Current(index.len as i64))?;
+
+        let mut stream = Stream::from_reader(reader);
+        stream.index = index;
+        stream.eof = true;
+        Ok(stream)
+    }
+}
+
+impl<R: io::Read> io::Read for Stream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        io::BufRead::consume(self, n);
+        Ok(n)
+    }
+}
+
+impl<R: io::Read> io::BufRead for Stream<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.reading && self.index.len > 0 {
+            return Err(Error::ReadAfterLookup {
+                skipped: self.read_len(),
+            }
+            .into());
+        }
+        self.reading = true;
+        if self.pending_pos == self.pending.len() {
+            self.pending.clear();
+            self.pending_pos = 0;
+            self.slide();
+            self.forward()?;
+        }
+        Ok(&self.pending[self.pending_pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pending_pos = (self.pending_pos + amt).min(self.pending.len());
+    }
+}
+
+impl<R: io::Read> Fill for Stream<R> {
+    #[inline]
+    fn index(&self) -> &LineIndex {
+        &self.index
+    }
+
+    #[inline]
+    fn fill_more(&mut self) -> Result<usize> {
+        Ok(self.forward()?)
+    }
+
+    #[inline]
+    fn fill(&mut self, need: Need) -> Result<()> {
+        self.fill_keeping(need, usize::MAX)
+    }
 }
 
 impl<R: io::Read> From<R> for Stream<R> {
@@ -138,48 +699,140 @@ impl<R: io::Read> From<R> for Stream<R> {
     }
 }
 
-/// Assuming `xs` is ordered, try to find a interval where `x` lies.  
-/// returns the start index of interval
-fn binary_search_between(xs: &[usize], x: usize) -> Option<usize> {
-    if xs.is_empty() {
-        return None;
-    }
-    if x == 0 {
-        return Some(0);
-    }
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-    let mut start = 0;
-    let mut end = xs.len() - 1;
-    while start < end {
-        // base case
-        if start == end - 1 && xs[start] <= x && x < xs[end] {
-            return Some(start);
-        }
+/// Errors of [`Stream`]
+#[derive(Debug)]
+pub enum Error {
+    /// The offset is not less than the length of the whole input
+    OffsetPastEof {
+        offset: usize,
+        /// End of the input, including the base offset
+        len: usize,
+    },
+    /// The offset lies before the base offset
+    OffsetBeforeBase { offset: usize, base: usize },
+    /// The offset lies before the window of a windowed stream, which starts at `start`
+    OffsetEvicted { offset: usize, start: usize },
+    /// The line does not exist
+    LineOutOfRange { line: usize, lines: usize },
+    /// The location lies before the base location
+    LocationBeforeBase { line: usize, column: usize },
+    /// The line lies before the window of a windowed stream, whose first line is `first`
+    LineEvicted { line: usize, first: usize },
+    /// The column exceeds the length of its line
+    ColumnOutOfRange {
+        line: usize,
+        column: usize,
+        /// Line length in columns, excluding the line break
+        width: usize,
+    },
+    /// The span starts after its end
+    InvalidSpan { start: usize, end: usize },
+    /// Bytes from `offset` are not kept in memory, only the first `retained` bytes are
+    NotRetained { offset: usize, retained: usize },
+    /// Lookups read `skipped` bytes before the stream was first read through `io::Read`,
+    /// those bytes cannot be read any more
+    ReadAfterLookup { skipped: usize },
+    /// The input is longer than the `len` bytes it was declared with, `read` bytes were read
+    InputTooLong { len: usize, read: usize },
+    /// The LSP position encoding is not one of `utf-8`, `utf-16` or `utf-32`
+    UnknownPositionEncoding(String),
+    /// No LSP position encoding counts columns in this unit
+    UnsupportedColumnUnit(ColumnUnit),
+    /// The line or column does not fit in the `u32` of an LSP position
+    PositionOverflow(usize),
+    /// The saved line index does not match its source
+    StaleIndex,
+    /// The saved line index is malformed
+    InvalidIndex(&'static str),
+    /// Failed to read the underlying reader
+    Io(io::Error),
+}
 
-        // binary search
-        let mid = start + ((end - start) >> 1);
-        let y = xs[mid];
-        if x == y {
-            return Some(mid);
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OffsetPastEof { offset, len } => {
+                write!(f, "Invalid offset {}, exceed EOF at {}", offset, len)
+            }
+            Error::OffsetBeforeBase { offset, base } => {
+                write!(f, "Invalid offset {}, before base at {}", offset, base)
+            }
+            Error::OffsetEvicted { offset, start } => {
+                write!(f, "Offset {} is evicted, the window starts at {}", offset, start)
+            }
+            Error::LineOutOfRange { line, lines } => {
+                write!(f, "Invalid line index {}, there are {} lines", line, lines)
+            }
+            Error::LocationBeforeBase { line, column } => {
+                write!(f, "Invalid location {}:{}, before base", line, column)
+            }
+            Error::LineEvicted { line, first } => {
+                write!(f, "Line {} is evicted, the window starts at line {}", line, first)
+            }
+            Error::ColumnOutOfRange {
+                line,
+                column,
+                width,
+            } => write!(
+                f,
+                "Invalid column {} on line {}, the line has {} columns",
+                column, line, width
+            ),
+            Error::InvalidSpan { start, end } => {
+                write!(f, "Invalid span {}..{}, start is after end", start, end)
+            }
+            Error::NotRetained { offset, retained } => write!(
+                f,
+                "Bytes from offset {} are not retained, only {} bytes are kept",
+                offset, retained
+            ),
+            Error::ReadAfterLookup { skipped } => write!(
+                f,
+                "Cannot read the stream, lookups already read {} bytes past it",
+                skipped
+            ),
+            Error::InputTooLong { len, read } => {
+                write!(f, "Input is longer than its length {}, read {} bytes", len, read)
+            }
+            Error::UnknownPositionEncoding(encoding) => {
+                write!(f, "Unknown position encoding: {}", encoding)
+            }
+            Error::UnsupportedColumnUnit(unit) => {
+                write!(f, "No position encoding for {:?}", unit)
+            }
+            Error::PositionOverflow(n) => write!(f, "Position exceeds u32: {}", n),
+            Error::StaleIndex => write!(f, "Saved line index does not match its source"),
+            Error::InvalidIndex(msg) => write!(f, "Invalid saved line index: {}", msg),
+            Error::Io(err) => err.fmt(f),
         }
+    }
+}
 
-        if x < y {
-            end = mid;
-            continue;
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
         }
-        // x > y
-        if start == mid {
-            return None;
-        }
-        start = mid;
     }
+}
 
-    None
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
 }
 
-#[inline]
-fn io_error<S: ToString>(msg: S) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, msg.to_string())
+/// Errors other than [`Error::Io`] become [`io::ErrorKind::Other`], as they were before they were typed
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::other(err),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -188,13 +841,25 @@ mod test {
     use std::fs::File;
 
     use super::*;
+    use quickcheck::{quickcheck, TestResult};
 
-    #[test]
-    fn test_binary_search() {
-        let xs = [2, 3];
-        let x = 1;
-        let i = binary_search_between(&xs, x);
-        dbg!(i);
+    /// Line and column of every character boundary, found by walking the text
+    fn naive_locations(text: &str, unit: ColumnUnit) -> Vec<(usize, (usize, usize))> {
+        let (mut line, mut column) = (0, 0);
+        let mut locations = Vec::new();
+        for (offset, c) in text.char_indices() {
+            locations.push((offset, (line, column)));
+            if c == '\n' {
+                (line, column) = (line + 1, 0);
+                continue;
+            }
+            column += match unit {
+                ColumnUnit::Byte => c.len_utf8(),
+                ColumnUnit::Utf16 => c.len_utf16(),
+                _ => 1,
+            };
+        }
+        locations
     }
 
     #[test]
@@ -207,7 +872,7 @@ mod test {
 
     #[test]
     fn test_stream_file() {
-        let file = File::open("/Users/comcx/Workspace/Repo/stream-locate-converter/Cargo.toml")
+        let file = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
             .expect("Failed to open file");
         let mut stream = Stream::from(file);
         let ans = stream.line_index(Offset::new(50));
@@ -219,11 +884,348 @@ mod test {
 
     #[test]
     fn test_stream_drain() {
-        let file = File::open("/Users/comcx/Workspace/Repo/stream-locate-converter/Cargo.toml")
+        let file = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
             .expect("Failed to open file");
         let mut stream = Stream::from(file);
         let ans = stream.drain();
         dbg!(ans);
-        dbg!(stream.lines);
+        dbg!(stream.index.lines);
+    }
+
+    #[test]
+    fn test_column_units() {
+        // Buffer size 2 splits multi-byte characters across reads
+        let text = "a\u{e9}\u{1f600}b\nc\u{e9}";
+        let stream = |unit| Stream::new(text.as_bytes(), 2).with_column_unit(unit);
+
+        let mut bytes = stream(ColumnUnit::Byte);
+        assert_eq!(bytes.line_index(Offset::new(7)).unwrap().raw(), (0, 7));
+
+        let mut chars = stream(ColumnUnit::Char);
+        assert_eq!(chars.line_index(Offset::new(7)).unwrap().raw(), (0, 3));
+        assert_eq!(chars.line_index(Offset::new(4)).unwrap().raw(), (0, 2));
+        assert_eq!(chars.offset_of((0, 3).into()).unwrap(), Offset::new(7));
+        assert_eq!(chars.offset_of((1, 2).into()).unwrap(), Offset::new(12));
+
+        let mut utf16 = stream(ColumnUnit::Utf16);
+        assert_eq!(utf16.line_index(Offset::new(7)).unwrap().raw(), (0, 4));
+        assert_eq!(utf16.offset_of((0, 3).into()).unwrap(), Offset::new(3));
+        assert_eq!(utf16.offset_of((0, 4).into()).unwrap(), Offset::new(7));
+    }
+
+    #[cfg(feature = "grapheme")]
+    #[test]
+    fn test_column_graphemes() {
+        let text = "e\u{301}x\n";
+        let mut stream = Stream::new(text.as_bytes(), 2).with_column_unit(ColumnUnit::Grapheme);
+        assert_eq!(stream.line_index(Offset::new(1)).unwrap().raw(), (0, 0));
+        assert_eq!(stream.line_index(Offset::new(3)).unwrap().raw(), (0, 1));
+        assert_eq!(stream.offset_of((0, 1).into()).unwrap(), Offset::new(3));
+    }
+
+    #[test]
+    fn test_offset_of_bounds() {
+        let reader = "ab\ncd\n";
+        let mut stream = Stream::from(reader.as_bytes());
+        assert_eq!(stream.offset_of((0, 5).into()).unwrap(), Offset::new(5));
+        assert_eq!(stream.offset_of_strict((0, 2).into()).unwrap(), Offset::new(2));
+        assert_eq!(stream.offset_of_clamped((0, 5).into()).unwrap(), Offset::new(2));
+        assert_eq!(stream.offset_of_clamped((2, 5).into()).unwrap(), Offset::new(6));
+
+        let err = stream.offset_of_strict((0, 5).into());
+        assert!(matches!(err, Err(Error::ColumnOutOfRange { width: 2, .. })));
+        let err = stream.offset_of_strict((3, 0).into());
+        assert!(matches!(err, Err(Error::LineOutOfRange { line: 3, lines: 3 })));
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let reader = "ab\r\ncd\ref";
+        let mut stream = Stream::from(reader.as_bytes()).with_line_ending(LineEnding::Any);
+        assert_eq!(stream.line_index(Offset::new(5)).unwrap().raw(), (1, 1));
+        assert_eq!(stream.line_index(Offset::new(8)).unwrap().raw(), (2, 1));
+        assert_eq!(stream.offset_of_clamped((0, 9).into()).unwrap(), Offset::new(2));
+        assert_eq!(stream.offset_of_clamped((1, 9).into()).unwrap(), Offset::new(6));
+
+        let mut stream = Stream::from(reader.as_bytes());
+        assert_eq!(stream.offset_of_clamped((0, 9).into()).unwrap(), Offset::new(3));
+        assert_eq!(stream.line_index(Offset::new(8)).unwrap().raw(), (1, 4));
+    }
+
+    #[test]
+    fn test_span_location() {
+        let reader = "ab\ncd\nef";
+        let mut stream = Stream::from(reader.as_bytes());
+        let span = Span::new(Offset::new(1), Offset::new(7));
+        let (start, end) = stream.span_location(span).unwrap();
+        assert_eq!((start.raw(), end.raw()), ((0, 1), (2, 1)));
+        assert_eq!(end.one_based().raw(), (3, 2));
+
+        let span = Span::new(Offset::new(3), Offset::new(8));
+        let (start, end) = stream.span_location(span).unwrap();
+        assert_eq!((start.raw(), end.raw()), ((1, 0), (2, 2)));
+
+        let span = Span::new(Offset::new(3), Offset::new(9));
+        assert!(matches!(stream.span_location(span), Err(Error::OffsetPastEof { .. })));
+        let (start, end) = (Offset::new(7), Offset::new(1));
+        let reversed = stream.span_location(Span { start, end });
+        assert!(matches!(reversed, Err(Error::InvalidSpan { start: 7, end: 1 })));
+    }
+
+    #[test]
+    fn test_fetch() {
+        let reader = io::Cursor::new("ab\r\ncd\nef");
+        let mut stream = Stream::from(reader).with_line_ending(LineEnding::CrLf);
+        assert_eq!(stream.line_index(Offset::new(5)).unwrap().raw(), (1, 1));
+        assert_eq!(stream.fetch_line_text(0).unwrap(), "ab");
+        assert_eq!(stream.fetch_line_text(2).unwrap(), "ef");
+        assert_eq!(stream.fetch_line(1).unwrap(), b"cd");
+        let span = Span::new(Offset::new(1), Offset::new(6));
+        assert_eq!(stream.fetch_span_text(span).unwrap(), "b\r\ncd");
+        assert!(matches!(stream.fetch_line(3), Err(Error::LineOutOfRange { .. })));
+        let (start, end) = (Offset::new(6), Offset::new(1));
+        assert!(matches!(stream.fetch_span(Span { start, end }), Err(Error::InvalidSpan { .. })));
+    }
+
+    #[test]
+    fn test_retained_source() {
+        let reader = "ab\ncd\nef";
+        let mut stream = Stream::new(reader.as_bytes(), 2).with_retained_source(7);
+        assert_eq!(stream.line_text(1).unwrap(), "cd");
+        let span = Span::new(Offset::new(1), Offset::new(5));
+        assert_eq!(stream.slice(span).unwrap().as_ref(), b"b\ncd");
+        let (start, end) = (Offset::new(5), Offset::new(1));
+        assert!(matches!(stream.slice(Span { start, end }), Err(Error::InvalidSpan { .. })));
+
+        let err = stream.line_text(2);
+        assert!(matches!(err, Err(Error::NotRetained { offset: 7, retained: 7 })));
+        assert_eq!(stream.retained_len(), 7);
+        let mut stream = Stream::from(reader.as_bytes());
+        assert!(matches!(stream.line_text(0), Err(Error::NotRetained { .. })));
+    }
+
+    #[test]
+    fn test_into_index() {
+        let reader = "a\u{e9}\nb\nc";
+        let stream = Stream::new(reader.as_bytes(), 2).with_column_unit(ColumnUnit::Char);
+        let index = std::sync::Arc::new(stream.into_index().unwrap());
+
+        let workers: Vec<_> = (0..3)
+            .map(|i| {
+                let index = index.clone();
+                std::thread::spawn(move || index.line_index(Offset::new(i * 2 + 1)).unwrap())
+            })
+            .collect();
+        let found: Vec<_> = workers.into_iter().map(|w| w.join().unwrap().raw()).collect();
+        assert_eq!(found, [(0, 1), (0, 2), (1, 1)]);
+        assert_eq!(index.offset_of((0, 2).into()).unwrap(), Offset::new(3));
+        assert!(index.line_of(Offset::new(7)).is_err());
+    }
+
+    #[test]
+    fn test_read_passthrough() {
+        use std::io::{BufRead, Read};
+
+        let reader = "fn main() {\n    let x = 1;\n}\n";
+        let mut stream = Stream::new(reader.as_bytes(), 4);
+        let mut first = String::new();
+        stream.read_line(&mut first).unwrap();
+        assert_eq!(first, "fn main() {\n");
+        assert_eq!(stream.consumed(), Offset::new(12));
+
+        // Look ahead, the bytes stay readable
+        assert_eq!(stream.offset_of((2, 0).into()).unwrap(), Offset::new(27));
+        let mut word = [0; 8];
+        stream.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"    let ");
+        let position = stream.consumed();
+        assert_eq!(stream.line_index(position).unwrap().raw(), (1, 8));
+
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "x = 1;\n}\n");
+        assert_eq!(stream.consumed(), Offset::new(reader.len()));
+
+        // Bytes read by a lookup before the first read are never returned silently
+        let mut stream = Stream::from("hello\nworld\n".as_bytes());
+        assert_eq!(stream.line_index(Offset::new(2)).unwrap().raw(), (0, 2));
+        let err = stream.read_to_string(&mut String::new()).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*err, Error::ReadAfterLookup { skipped: 12 }));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let reader = "let \u{3bb} = 1;\r\n\n  x\u{1f600} + \u{3bb}\r\n";
+        let tokens = reader.split_inclusive(|c: char| c.is_whitespace() || c == ';');
+        for unit in [ColumnUnit::Byte, ColumnUnit::Char, ColumnUnit::Utf16] {
+            let stream = || {
+                Stream::new(reader.as_bytes(), 3)
+                    .with_column_unit(unit)
+                    .with_line_ending(LineEnding::CrLf)
+            };
+            let index = stream().into_index().unwrap();
+            let mut cursor = stream();
+            for token in tokens.clone() {
+                let (offset, location) = cursor.position().unwrap();
+                assert_eq!(index.line_index(offset).unwrap(), location);
+                cursor.advance(token.len()).unwrap();
+            }
+            assert_eq!(cursor.position().unwrap().0, Offset::new(reader.len()));
+            assert_eq!(cursor.position().unwrap().1.raw(), (3, 0));
+            assert!(cursor.advance(1).is_err());
+            let err = cursor.advance(usize::MAX);
+            assert!(matches!(err, Err(Error::OffsetPastEof { offset: usize::MAX, .. })));
+            assert_eq!(cursor.position().unwrap().0, Offset::new(reader.len()));
+        }
+    }
+
+    #[test]
+    fn test_base() {
+        // A snippet starting at offset 20, line 3, column 4 of the enclosing document
+        let snippet = "x = 1;\ny = 2;\n";
+        let mut stream = Stream::from(snippet.as_bytes()).with_retained_source(64);
+        stream.set_base_location(Offset::new(20), (3, 4).into());
+        assert_eq!(stream.line_index(Offset::new(22)).unwrap().raw(), (3, 6));
+        assert_eq!(stream.line_index(Offset::new(28)).unwrap().raw(), (4, 1));
+        assert_eq!(stream.line_of(Offset::new(28)).unwrap(), 4);
+        assert_eq!(stream.offset_of((3, 5).into()).unwrap(), Offset::new(21));
+        assert_eq!(stream.offset_of_clamped((4, 9).into()).unwrap(), Offset::new(33));
+        assert_eq!(stream.line_offset(4), Some(Offset::new(27)));
+        assert_eq!(stream.line_text(4).unwrap(), "y = 2;");
+
+        let span = Span::new(Offset::new(24), Offset::new(29));
+        let (start, end) = stream.span_location(span).unwrap();
+        assert_eq!((start.raw(), end.raw()), ((3, 8), (4, 2)));
+        assert_eq!(stream.slice(span).unwrap().as_ref(), b"1;\ny ");
+
+        let err = stream.line_index(Offset::new(19));
+        assert!(matches!(err, Err(Error::OffsetBeforeBase { offset: 19, base: 20 })));
+        let err = stream.offset_of((3, 3).into());
+        assert!(matches!(err, Err(Error::LocationBeforeBase { line: 3, column: 3 })));
+        let err = stream.line_index(Offset::new(34));
+        assert!(matches!(err, Err(Error::OffsetPastEof { offset: 34, len: 34 })));
+        let err = stream.offset_of_strict((6, 0).into());
+        assert!(matches!(err, Err(Error::LineOutOfRange { line: 6, lines: 6 })));
+
+        stream.advance(8).unwrap();
+        assert_eq!(stream.position().unwrap(), (Offset::new(28), (4, 1).into()));
+        let index = stream.into_index().unwrap();
+        assert_eq!(index.line_index(Offset::new(22)).unwrap().raw(), (3, 6));
+
+        let mut stream = Stream::from(snippet.as_bytes());
+        stream.set_base(100);
+        assert_eq!(stream.line_index(Offset::new(108)).unwrap().raw(), (1, 1));
+        stream.reset();
+        assert_eq!(stream.line_index(Offset::new(8)).unwrap().raw(), (1, 1));
+    }
+
+    #[test]
+    fn test_visual_columns() {
+        let text = "fn f() {\n\tlet x\t= 1;\n}\n";
+        let tabs = TabStops::new(4);
+        let mut stream = Stream::new(text.as_bytes(), 4)
+            .with_retained_source(64)
+            .with_tab_stops(tabs.clone());
+        // `x` is at byte 5 of line 1, after a tab and `let `
+        assert_eq!(stream.visual_location(Offset::new(14)).unwrap().raw(), (1, 8));
+        assert_eq!(stream.line_index(Offset::new(14)).unwrap().raw(), (1, 5));
+        // `=` follows a tab from column 9 to 12
+        assert_eq!(stream.visual_location(Offset::new(16)).unwrap().raw(), (1, 12));
+        assert_eq!(stream.offset_of_visual((1, 10).into()).unwrap(), Offset::new(15));
+        assert_eq!(stream.offset_of_visual((1, 12).into()).unwrap(), Offset::new(16));
+        assert_eq!(stream.offset_of_visual((1, 99).into()).unwrap(), Offset::new(20));
+
+        let mut stream = Stream::from(io::Cursor::new(text)).with_tab_stops(tabs.with_stops([2]));
+        assert_eq!(stream.fetch_visual_location(Offset::new(14)).unwrap().raw(), (1, 6));
+        assert_eq!(stream.fetch_offset_of_visual((1, 6).into()).unwrap(), Offset::new(14));
+        assert!(matches!(stream.visual_location(Offset::new(14)), Err(Error::NotRetained { .. })));
+    }
+
+    quickcheck! {
+        fn prop_round_trip(picks: Vec<u8>, queries: Vec<usize>, buffer_size: u8, unit: u8)
+            -> TestResult
+        {
+            if picks.is_empty() {
+                return TestResult::discard();
+            }
+            let chars = ['a', '\n', '\u{e9}', '\u{1f600}'];
+            let text: String = picks.iter().map(|&i| chars[i as usize % 4]).collect();
+            let unit = [ColumnUnit::Byte, ColumnUnit::Char, ColumnUnit::Utf16][unit as usize % 3];
+            let locations = naive_locations(&text, unit);
+            let mut stream = Stream::new(text.as_bytes(), buffer_size as usize % 16 + 1)
+                .with_column_unit(unit);
+            // Lookups jump back and forth, so some need more bytes and some do not
+            for query in queries {
+                let (offset, (line, column)) = locations[query % locations.len()];
+                let offset = Offset::new(offset);
+                let location = stream.line_index(offset).unwrap();
+                if location.raw() != (line, column) || stream.line_of(offset).unwrap() != line {
+                    return TestResult::failed();
+                }
+                if stream.offset_of(location).unwrap() != offset {
+                    return TestResult::failed();
+                }
+            }
+            TestResult::from_bool(stream.line_index(Offset::new(text.len())).is_err())
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let log: String = (0..20).map(|i| format!("entry {} \u{e9}\n", i)).collect();
+        let mut stream = Stream::new(log.as_bytes(), 4)
+            .with_column_unit(ColumnUnit::Char)
+            .with_window_lines(3);
+        let mut start = 0;
+        for (line, text) in log.lines().enumerate() {
+            let offset = stream.offset_of((line, 0).into()).unwrap();
+            assert_eq!(offset, Offset::new(start));
+            // Inside the last character
+            let location = stream.line_index(Offset::new(start + text.len() - 1)).unwrap();
+            assert_eq!(location.raw(), (line, text.chars().count() - 1));
+            start += text.len() + 1;
+        }
+        let err = stream.line_of(Offset::new(0));
+        assert!(matches!(err, Err(Error::OffsetEvicted { offset: 0, .. })));
+        let err = stream.offset_of((16, 0).into());
+        assert!(matches!(err, Err(Error::LineEvicted { line: 16, first: 18 })));
+        assert_eq!(stream.index.line_count(), 3);
+        assert_eq!(stream.line_count(), 21);
+        assert_eq!(stream.line_offset(19), Some(Offset::new(log.len() - 12)));
+
+        let mut stream = Stream::new(log.as_bytes(), 16).with_window_bytes(32);
+        let mut text = String::new();
+        io::Read::read_to_string(&mut stream, &mut text).unwrap();
+        assert_eq!(text, log);
+        assert_eq!(stream.consumed(), Offset::new(log.len()));
+        assert!(stream.index.len() - stream.index.base.stale < 32 + 12);
+        assert_eq!(stream.line_index(Offset::new(log.len() - 5)).unwrap().raw(), (19, 7));
+        assert!(matches!(stream.offset_of((0, 0).into()), Err(Error::LineEvicted { .. })));
+
+        // The cursor is not moved when its line is evicted
+        let mut stream = Stream::new(log.as_bytes(), 4).with_window_lines(2);
+        stream.advance(3).unwrap();
+        stream.offset_of((10, 0).into()).unwrap();
+        let err = stream.advance(1);
+        assert!(matches!(err, Err(Error::OffsetEvicted { offset: 3, .. })));
+        assert!(matches!(stream.position(), Err(Error::OffsetEvicted { offset: 3, .. })));
+
+        // Lines are evicted while a lookup reads far ahead
+        let blank = || io::Read::take(io::repeat(b'\n'), 1_000_000);
+        let mut stream = Stream::new(blank(), 16).with_window_lines(3);
+        assert_eq!(stream.offset_of((999_999, 0).into()).unwrap(), Offset::new(999_999));
+        assert!(stream.index.lines.len() < 64);
+        assert_eq!(stream.line_index(Offset::new(999_998)).unwrap().raw(), (999_998, 0));
+        let err = stream.offset_of((999_990, 0).into());
+        assert!(matches!(err, Err(Error::LineEvicted { first: 999_998, .. })));
+
+        let mut stream = Stream::new(blank(), 16).with_window_lines(3);
+        stream.advance(999_999).unwrap();
+        let (offset, location) = stream.position().unwrap();
+        assert_eq!((offset.raw(), location.raw()), (999_999, (999_999, 0)));
+        let mut saved = vec![];
+        let err = Stream::new(io::Cursor::new(log), 16).with_window_lines(3).save_index(&mut saved);
+        assert!(matches!(err, Err(Error::LineEvicted { line: 0, first: 18 })));
     }
 }
@@ -0,0 +1,131 @@
+//! Visual columns, where a tab advances to the next tab stop.
+//!
+//! Every other character takes one column, bytes which are not part of a valid UTF-8 sequence
+//! take one column each, so visual columns match [`crate::column::ColumnUnit::Char`] on lines
+//! without tabs.
+
+/// Where tabs stop: at explicit columns first, then every `width` columns after the last one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TabStops {
+    stops: Vec<usize>,
+    width: usize,
+}
+
+impl Default for TabStops {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl TabStops {
+    /// Tab stops every `width` columns, which must be positive
+    pub fn new(width: usize) -> Self {
+        assert!(width > 0, "tab width must be positive");
+        Self {
+            stops: Vec::new(),
+            width,
+        }
+    }
+
+    /// Stop tabs at `stops` first, every `width` columns after the last of them
+    pub fn with_stops(mut self, stops: impl IntoIterator<Item = usize>) -> Self {
+        self.stops = stops.into_iter().filter(|&stop| stop > 0).collect();
+        self.stops.sort_unstable();
+        self.stops.dedup();
+        self
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn stops(&self) -> &[usize] {
+        &self.stops
+    }
+
+    /// Column reached by a tab at `column`
+    pub fn next_stop(&self, column: usize) -> usize {
+        let i = self.stops.partition_point(|&stop| stop <= column);
+        if let Some(&stop) = self.stops.get(i) {
+            return stop;
+        }
+        let last = self.stops.last().copied().unwrap_or(0);
+        last + ((column - last) / self.width + 1) * self.width
+    }
+
+    /// Visual column of `offset` in `line`, both relative to the line start.
+    /// An offset inside a character resolves to the column of that character.
+    pub fn column(&self, line: &[u8], offset: usize) -> usize {
+        let mut column = 0;
+        for (_, end, tab) in units(line) {
+            if end > offset {
+                break;
+            }
+            column = self.advance(column, tab);
+        }
+        column
+    }
+
+    /// Offset in `line` of a visual column, relative to the line start.
+    /// A column inside a tab resolves to the tab, a column past the line to its end.
+    pub fn offset(&self, line: &[u8], column: usize) -> usize {
+        let mut current = 0;
+        for (start, _, tab) in units(line) {
+            let next = self.advance(current, tab);
+            if next > column {
+                return start;
+            }
+            current = next;
+        }
+        line.len()
+    }
+
+    #[inline]
+    fn advance(&self, column: usize, tab: bool) -> usize {
+        match tab {
+            true => self.next_stop(column),
+            false => column + 1,
+        }
+    }
+}
+
+/// Start, end and whether it is a tab, for every character of `line`
+fn units(line: &[u8]) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+    let mut pos = 0;
+    line.utf8_chunks().flat_map(move |chunk| {
+        let start = pos;
+        pos += chunk.valid().len() + chunk.invalid().len();
+        let valid = chunk.valid().char_indices();
+        let valid = valid.map(move |(i, c)| (start + i, start + i + c.len_utf8(), c == '\t'));
+        let invalid_start = start + chunk.valid().len();
+        let invalid = (0..chunk.invalid().len()).map(move |i| {
+            let pos = invalid_start + i;
+            (pos, pos + 1, false)
+        });
+        valid.chain(invalid)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tab_stops() {
+        let tabs = TabStops::new(4);
+        assert_eq!((tabs.next_stop(0), tabs.next_stop(3), tabs.next_stop(4)), (4, 4, 8));
+        let tabs = TabStops::new(4).with_stops([10, 2, 2]);
+        assert_eq!(tabs.stops(), &[2, 10]);
+        assert_eq!((tabs.next_stop(0), tabs.next_stop(2), tabs.next_stop(11)), (2, 10, 14));
+
+        // `\t` from 0 to 4, `é` at 4, `\t` from 5 to 8, `x` at 8, an invalid byte at 9
+        let line = b"\t\xc3\xa9\tx\xff";
+        let tabs = TabStops::new(4);
+        let columns: Vec<_> = (0..=line.len()).map(|offset| tabs.column(line, offset)).collect();
+        assert_eq!(columns, [0, 4, 4, 5, 8, 9, 10]);
+        let offsets: Vec<_> = (0..=11).map(|column| tabs.offset(line, column)).collect();
+        assert_eq!(offsets, [0, 0, 0, 0, 1, 3, 3, 3, 4, 5, 6, 6]);
+    }
+}